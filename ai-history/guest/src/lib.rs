@@ -1,150 +1,496 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::collections::VecDeque;
+use crate::wasi::keyvalue::atomics;
+use crate::wasi::keyvalue::store::{self, Bucket};
+use guest_common::log;
+use guest_common::similarity::cosine_similarity;
+
 
 const MAX_ITEMS: usize = 1000;
+// Every entry and session is kept under a key of its own, so that guests
+// adding to the history at the same time never overwrite each other
+const ENTRY_PREFIX: &str = "entry/";
+const SESSION_PREFIX: &str = "session/";
+// Counts what was added so far, numbering entries and sessions in order
+const SEQUENCE_KEY: &str = "sequence";
+// Counts the sessions created so far, numbering the next one
+const LAST_SESSION_KEY: &str = "last-session";
+// The key the whole history was kept under before, and the counter that
+// picks the one guest that moves it to keys of their own
+const HISTORY_KEY: &str = "history";
+const MIGRATED_KEY: &str = "migrated";
+// The session of entries cached before there were sessions
+const DEFAULT_SESSION: &str = "default";
 
 // An cached entry from the AI model
 #[derive(Serialize, Deserialize)]
 struct Entry {
+    // The session the prompt was asked in
+    #[serde(default = "default_session")]
+    session: String,
     model: String,
     prompt: String,
     response: String,
     context: Vec<u64>,
+    // Embedding of the prompt, kept when prompts are matched by meaning
+    #[serde(default)]
+    embedding: Option<Vec<f32>>,
+    // Hash of the context the prompt was asked with, telling the conversations apart
+    #[serde(default)]
+    asked_in: Option<u64>,
+    // The options the answer was generated with
+    #[serde(default)]
+    options: Settings,
+    // The seed the answer was generated with, drawn when the options set none
+    #[serde(default)]
+    seed: Option<u64>,
+    // Whether the model was asked with chat messages rather than context tokens
+    #[serde(default)]
+    chat: bool,
 }
 
-// The list of entries as a doubly linked list
-#[derive(Serialize, Deserialize)]
-struct Data {
-    entries: VecDeque<Entry>,
+// The generation options, as kept in the store
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+struct Settings {
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    seed: Option<u64>,
+    max_tokens: Option<u32>,
+    system: Option<String>,
+    stop: Vec<String>,
+}
+
+impl Settings {
+    // The options to generate the answer with again
+    fn options(&self, seed: Option<u64>) -> Options {
+        Options {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            seed,
+            max_tokens: self.max_tokens,
+            system: self.system.clone(),
+            stop: self.stop.clone(),
+        }
+    }
 }
 
-// Just for creating new data
-impl Data {
-    fn new() -> Self {
-        Data {
-            entries: VecDeque::new(),
+impl From<&Options> for Settings {
+    fn from(options: &Options) -> Self {
+        Settings {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+            max_tokens: options.max_tokens,
+            system: options.system.clone(),
+            stop: options.stop.clone(),
         }
     }
 }
 
-// To structure our functions that nead file and serialization operations.
+// The whole history, as it was kept under one key
+#[derive(Deserialize)]
+struct Data {
+    entries: VecDeque<Entry>,
+    #[serde(default)]
+    sessions: Vec<Session>,
+    // Counts the sessions created so far, numbering the next one
+    #[serde(default)]
+    last_session: u64,
+}
+
+// A conversation, with its own chain of context per model
+#[derive(Serialize, Deserialize)]
+struct Session {
+    id: String,
+    name: String,
+    // Where the session was started in the sequence, ordering the sessions
+    #[serde(default)]
+    order: u64,
+}
+
+fn default_session() -> String {
+    DEFAULT_SESSION.to_string()
+}
+
+// To structure our functions that nead storage and serialization operations.
 pub struct FileCache {
-    file_path: String,
+    bucket: Bucket,
 }
 
 impl FileCache {
-    // Create a new file cache object that only includes a path as string
-    pub fn new(file_path: impl Into<String>) -> Self {
-        FileCache {
-            file_path: file_path.into(),
+    // Open the key-value bucket that holds the cache, None if the host refuses it
+    pub fn open(file_path: &str) -> Option<Self> {
+        let cache = store::open(file_path).ok().map(|bucket| FileCache { bucket })?;
+        cache.migrate();
+        Some(cache)
+    }
+
+    // Moves a history kept under one key to keys of their own. Only the first
+    // guest to count the migration does it.
+    fn migrate(&self) {
+        let Some(data) = self.get::<Data>(HISTORY_KEY) else {
+            return;
+        };
+        if !matches!(atomics::increment(&self.bucket, MIGRATED_KEY, 1), Ok(1)) {
+            return;
+        }
+        let _ = atomics::increment(&self.bucket, LAST_SESSION_KEY, data.last_session);
+        for mut session in data.sessions {
+            session.order = self.next_in_sequence();
+            self.put(&format!("{}{}", SESSION_PREFIX, session.id), &session);
         }
+        for entry in data.entries {
+            self.add_response(entry);
+        }
+        let _ = self.bucket.delete(HISTORY_KEY);
     }
 
-    // Loads the cache data from disk.
-    fn load_cache(&self) -> Data {
-        let contents = host::read_from_file(&self.file_path);
-        // If the file can’t be read or parsed, returns an empty cache.
-        if let Ok(data) = serde_json::from_str(&contents) {
-            return data;
+    // Reads and parses the value of a key, None if it is missing or can't be parsed
+    fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let contents = self.bucket.get(key).ok().flatten()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    fn put<T: Serialize>(&self, key: &str, value: &T) {
+        if let Ok(json) = serde_json::to_vec(value) {
+            let _ = self.bucket.set(key, &json);
         }
-        Data::new()
     }
 
-    // Saves the cache data to disk.
-    fn save_cache(&self, data: &Data) {
-        if let Ok(json) = serde_json::to_string(data) {
-            host::write_to_file(&json, &self.file_path);
+    // The next number of the sequence, the same for no two calls
+    fn next_in_sequence(&self) -> u64 {
+        atomics::increment(&self.bucket, SEQUENCE_KEY, 1).unwrap_or_default()
+    }
+
+    // The keys starting with the prefix, in order
+    fn keys(&self, prefix: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        while let Ok(page) = self.bucket.list_keys(cursor) {
+            keys.extend(page.keys.into_iter().filter(|key| key.starts_with(prefix)));
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
         }
+        keys
     }
 
-    // Adds or updates a cache entry.
-    pub fn add_response(&self, model: &str, prompt: &str, response: &str, context: &Vec<u64>) {
-        let mut data = self.load_cache();
-        let entry = Entry {
-            model: model.to_string(),
-            prompt: prompt.to_string(),
-            response: response.to_string(),
-            context: context.clone(),
-        };
-        // If the cache list was overflowing the maximum allowed cache entries
-        // then remove the oldest entry
-        if data.entries.len() >= MAX_ITEMS {
-            data.entries.pop_front(); // Remove the oldest item
+    // Every entry, oldest first. If one can't be read or parsed it is left out.
+    fn entries(&self) -> Vec<Entry> {
+        self.keys(ENTRY_PREFIX).iter().filter_map(|key| self.get(key)).collect()
+    }
+
+    fn load_sessions(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.keys(SESSION_PREFIX).iter().filter_map(|key| self.get(key)).collect();
+        sessions.sort_by_key(|session| session.order);
+        sessions
+    }
+
+    // Registers a session that is used without having been created, naming it after its id
+    fn register_session(&self, id: &str) {
+        let key = format!("{}{}", SESSION_PREFIX, id);
+        if !self.bucket.exists(&key).unwrap_or(false) {
+            let order = self.next_in_sequence();
+            self.put(&key, &Session { id: id.to_string(), name: id.to_string(), order });
+        }
+    }
+
+    // Adds a cache entry.
+    fn add_response(&self, entry: Entry) {
+        self.register_session(&entry.session);
+        // Numbers are padded so that the keys list in the order they were added
+        let key = format!("{}{:020}", ENTRY_PREFIX, self.next_in_sequence());
+        self.put(&key, &entry);
+        // If the cache was overflowing the maximum allowed cache entries
+        // then remove the oldest ones
+        let keys = self.keys(ENTRY_PREFIX);
+        for oldest in keys.iter().take(keys.len().saturating_sub(MAX_ITEMS)) {
+            let _ = self.bucket.delete(oldest);
         }
-        // Add the entry to the end of the list
-        data.entries.push_back(entry);
-        self.save_cache(&data);
     }
 
-    // Retrieves a cached response if it exists and is fresh.
-    // Returns None on a cache miss or if the entry is stale.
-    pub fn get_response(&self, model: &str, prompt: &str) -> Option<String> {
-        let data = self.load_cache();
-        let prompt_lower = prompt.to_lowercase();
+    // Retrieves the cached response to a prompt of the model, matched with the given strategy,
+    // together with the prompt it matched. The semantic strategy needs the embedding of the prompt.
+    // With a context hash, only prompts asked in that same context are considered.
+    // Only answers generated with the same options are reused.
+    // Returns None on a cache miss.
+    fn get_response(
+        &self,
+        model: &str,
+        prompt: &str,
+        strategy: MatchStrategy,
+        embedding: Option<&[f32]>,
+        asked_in: Option<u64>,
+        options: &Settings,
+    ) -> Option<Answer> {
+        let entries = self.entries();
         let model_lower = model.to_lowercase();
-        data.entries
+        let mut entries = entries
             .iter()
-            .find(|entry| {
-                entry.model.to_lowercase() == model_lower
-                    && entry.prompt.to_lowercase().contains(&prompt_lower)
-            })
-            .map(|entry| entry.response.clone())
+            .filter(|entry| entry.model.to_lowercase() == model_lower)
+            .filter(|entry| asked_in.is_none() || entry.asked_in == asked_in)
+            .filter(|entry| entry.options == *options);
+        let found = match strategy {
+            MatchStrategy::Exact => entries.find(|entry| entry.prompt == prompt).map(|entry| (entry, 1.0)),
+            MatchStrategy::Normalized => {
+                let prompt = normalize(prompt);
+                entries.find(|entry| normalize(&entry.prompt) == prompt).map(|entry| (entry, 1.0))
+            }
+            MatchStrategy::Fuzzy(threshold) => {
+                let prompt = normalize(prompt);
+                best_match(entries.map(|entry| (entry, similarity(&normalize(&entry.prompt), &prompt))), threshold)
+            }
+            // Entries cached without an embedding can't be matched by meaning
+            MatchStrategy::Semantic(threshold) => {
+                let embedding = embedding?;
+                best_match(
+                    entries.filter_map(|entry| Some((entry, cosine_similarity(entry.embedding.as_deref()?, embedding)))),
+                    threshold,
+                )
+            }
+        };
+        found.map(|(entry, score)| Answer {
+            text: entry.response.clone(),
+            cached: Some(CacheMatch {
+                prompt: entry.prompt.clone(),
+                score,
+            }),
+        })
     }
 
-    // Retrieves the latest (newest) context from the cache
-    pub fn get_latest_context(&self, model: &str) -> Option<Vec<u64>> {
-        let data = self.load_cache();
-        data.entries
+    // Retrieves the latest (newest) context of the model in a session from the cache
+    pub fn get_latest_context(&self, session: &str, model: &str) -> Option<Vec<u64>> {
+        self.entries()
             .iter()
             .rev()
-            .find(|entry| entry.model.eq_ignore_ascii_case(model))
+            .find(|entry| entry.session == session && entry.model.eq_ignore_ascii_case(model))
             .map(|entry| entry.context.clone())
     }
 
-    // Clears all cache entries.
-    pub fn clear(&self) {
-        let data = Data::new();
-        self.save_cache(&data);
+    // The entries of a session, oldest first
+    fn session_entries(&self, session: &str) -> Vec<Entry> {
+        self.entries()
+            .into_iter()
+            .filter(|entry| entry.session == session)
+            .collect()
     }
 
-    // Extracts the response and the context from a json ollama response got from the host
-    fn parse_ollama_stream(&self, json_stream: &str) -> (String, Vec<u64>) {
-        let mut response = String::new();
-        let mut context = Vec::new();
+    // The exchanges of a session as chat messages, whichever model answered them
+    pub fn messages(&self, session: &str) -> Vec<Message> {
+        self.session_entries(session)
+            .into_iter()
+            .flat_map(|entry| {
+                [
+                    Message { role: Role::User, content: entry.prompt },
+                    Message { role: Role::Assistant, content: entry.response },
+                ]
+            })
+            .collect()
+    }
 
-        for line in json_stream.lines() {
-            if line.trim().is_empty() {
-                continue;
+    // Lists the exchanges of a session, oldest first
+    pub fn history(&self, session: &str) -> Vec<Exchange> {
+        self.session_entries(session)
+            .into_iter()
+            .map(|entry| Exchange {
+                model: entry.model,
+                prompt: entry.prompt,
+                response: entry.response,
+            })
+            .collect()
+    }
+
+    // Starts a new session and returns its id
+    pub fn create_session(&self, name: &str) -> String {
+        // Skip numbers already taken by sessions registered under them
+        let id = loop {
+            let id = atomics::increment(&self.bucket, LAST_SESSION_KEY, 1).unwrap_or_default().to_string();
+            if !self.bucket.exists(&format!("{}{}", SESSION_PREFIX, id)).unwrap_or(false) {
+                break id;
             }
+        };
+        let session = Session {
+            id: id.clone(),
+            name: name.to_string(),
+            order: self.next_in_sequence(),
+        };
+        self.put(&format!("{}{}", SESSION_PREFIX, id), &session);
+        id
+    }
 
-            match serde_json::from_str::<Value>(line) {
-                Ok(json) => {
-                    if let Some(text) = json["response"].as_str() {
-                        response.push_str(text);
-                    }
-
-                    if json.get("done") == Some(&Value::Bool(true)) {
-                        if let Some(ctx) = json.get("context").and_then(|v| v.as_array()) {
-                            context = ctx.iter().filter_map(|v| v.as_u64()).collect();
-                        }
-                    }
-                }
-                Err(err) => {
-                    continue;
-                }
+    // Lists the sessions in the order they were started
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let entries = self.entries();
+        self.load_sessions()
+            .into_iter()
+            .map(|session| SessionInfo {
+                exchanges: entries.iter().filter(|entry| entry.session == session.id).count() as u32,
+                id: session.id,
+                name: session.name,
+            })
+            .collect()
+    }
+
+    // Renames a session, false if there is no such session
+    pub fn rename_session(&self, id: &str, name: &str) -> bool {
+        let key = format!("{}{}", SESSION_PREFIX, id);
+        let Some(mut session) = self.get::<Session>(&key) else {
+            return false;
+        };
+        session.name = name.to_string();
+        self.put(&key, &session);
+        true
+    }
+
+    // Deletes a session together with its exchanges, false if there is no such session
+    pub fn delete_session(&self, id: &str) -> bool {
+        let key = format!("{}{}", SESSION_PREFIX, id);
+        if !self.bucket.exists(&key).unwrap_or(false) {
+            return false;
+        }
+        let _ = self.bucket.delete(&key);
+        for key in self.keys(ENTRY_PREFIX) {
+            if self.get::<Entry>(&key).is_some_and(|entry| entry.session == id) {
+                let _ = self.bucket.delete(&key);
+            }
+        }
+        true
+    }
+
+    // Clears all cache entries and sessions. The counters are kept, so that
+    // numbers are never handed out twice.
+    pub fn clear(&self) {
+        for key in self.keys(ENTRY_PREFIX).into_iter().chain(self.keys(SESSION_PREFIX)) {
+            let _ = self.bucket.delete(&key);
+        }
+    }
+}
+
+// The entry with the highest score, if that reaches the threshold
+fn best_match<'a>(scored: impl Iterator<Item = (&'a Entry, f32)>, threshold: f32) -> Option<(&'a Entry, f32)> {
+    scored
+        .filter(|(_, score)| *score >= threshold)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+// FNV-1a hash of the conversation, either its context tokens or its
+// messages. Prompts asked after the same conversation continue it.
+fn context_hash(context: &[u64], messages: &[Message]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let tokens = context.iter().flat_map(|token| token.to_le_bytes());
+    // Every message ends with a byte no UTF-8 text has, keeping them apart
+    let messages = messages
+        .iter()
+        .flat_map(|message| [message.role as u8].into_iter().chain(message.content.bytes()).chain([0xff]));
+    let mut hash = OFFSET;
+    for byte in tokens.chain(messages) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+// Lowercases a prompt and collapses its runs of whitespace into single spaces
+fn normalize(prompt: &str) -> String {
+    prompt
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Similarity of two prompts from 0 (nothing alike) to 1 (equal), from the
+// number of characters that have to change to turn one into the other
+fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(&a, &b) as f32 / longest as f32
+}
+
+// Levenshtein distance, keeping a single row of the table
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Starts asking the model with the conversation before the prompt: the context
+// tokens of the model for the generate API, the earlier messages for chat.
+fn start_answer(api: Api, model: &str, prompt: &str, context: &[u64], messages: &[Message], options: &Options) -> Option<ModelStream> {
+    match api {
+        Api::Generate => host::stream_model(model, prompt, context, options),
+        Api::Chat => {
+            let mut messages = messages.to_vec();
+            messages.push(Message { role: Role::User, content: prompt.to_string() });
+            host::stream_chat(model, &messages, options)
+        }
+    }
+}
+
+// Reads the answer the model streams, passing every token to the host as it
+// arrives when it is to be shown. Returns the whole answer and, from models that
+// take them, the context tokens of the conversation. None if the model gave no answer.
+fn stream_answer(stream: ModelStream, show: bool) -> Option<(String, Vec<u64>)> {
+    let mut response = String::new();
+    let mut new_context = Vec::new();
+    let mut answered = false;
+
+    loop {
+        // A partial answer is not worth keeping
+        let chunk = match stream.next() {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(reason) => {
+                log::warn("model", "the answer ended early", &[("reason", &reason)]);
+                return None;
             }
+        };
+        answered = true;
+        if show && !chunk.text.is_empty() {
+            host::print_token(&chunk.text);
+        }
+        if !chunk.text.is_empty() {
+            response.push_str(&chunk.text);
+        }
+        if let Some(context) = chunk.context {
+            new_context = context;
         }
-        // Return the extracted response and context
-        (response, context)
     }
+    // Return the extracted response and context
+    answered.then_some((response, new_context))
 }
 
 // Generate rust code from WIT
-wit_bindgen::generate!({
-    path: "wit",
-    world: "chat",
-});
+// The export shims wit-bindgen generates take one argument per flattened
+// value, which for `ask` is more than clippy allows.
+#[allow(clippy::too_many_arguments)]
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "chat",
+        generate_all,
+        pub_export_macro: true,
+    });
+}
+use bindings::*;
+
+use host::{Message, ModelStream, Role};
 
 // The exported host struct (For WIT)
 struct MyHost;
@@ -152,29 +498,145 @@ struct MyHost;
 // Implement the Guest trait for the exported struct
 impl Guest for MyHost {
     // Get the response to a prompt from cache if available otherwise get it from the AI model
-    fn ask(file_path: String, model: String, prompt: String) -> Option<String> {
-        let cache = FileCache::new(file_path);
+    fn ask(file_path: String, session: String, model: String, prompt: String, lookup: Lookup, api: Api, options: Options) -> Option<Answer> {
+        let cache = FileCache::open(&file_path)?;
+        // Only matching by meaning needs the embedding of the prompt
+        let embedding = match lookup.strategy {
+            MatchStrategy::Semantic(_) => host::embed(&prompt),
+            _ => None,
+        };
+        // The conversation so far in this session, which a new answer continues:
+        // the context tokens of the model, or the messages of every model before
+        let (latest_context, messages) = match api {
+            Api::Generate => (cache.get_latest_context(&session, &model).unwrap_or_default(), Vec::new()),
+            Api::Chat => (Vec::new(), cache.messages(&session)),
+        };
+        let asked_in = context_hash(&latest_context, &messages);
+        let in_context = lookup.in_context.then_some(asked_in);
+        let settings = Settings::from(&options);
         // If the prompt was previously asked and existed in the cache
-        if let Some(cached) = cache.get_response(&model, &prompt) {
-            Some(cached)
+        if let Some(answer) = cache.get_response(&model, &prompt, lookup.strategy, embedding.as_deref(), in_context, &settings) {
+            if let Some(found) = &answer.cached {
+                log::info("history", "answered from history", &[("session", &session), ("model", &model), ("prompt", &prompt), ("matched", &found.prompt), ("score", &found.score)]);
+            }
+            Some(answer)
         // If the prompt didn't exist in the cache
         } else {
-            let latest_context = cache.get_latest_context(&model).unwrap_or_default();
-            // Get the response from the AI model
-            match host::ask_model(&model, &prompt, &latest_context) {
-
-                Some(response_raw) => {
-                    let (response, new_context) = cache.parse_ollama_stream(&response_raw);
-                    cache.add_response(&model, &prompt, &response, &new_context);
-                    Some(response)
+            log::info("history", "not in history, asking the model", &[("session", &session), ("model", &model), ("context_tokens", &latest_context.len()), ("messages", &messages.len())]);
+            // Every answer is generated with a seed, so that replaying it can give the same answer
+            let seed = options.seed.unwrap_or_else(::wasi::random::random::get_random_u64);
+            let options = Options { seed: Some(seed), ..options };
+            let stream = start_answer(api, &model, &prompt, &latest_context, &messages, &options);
+            // Get the response from the AI model, the whole of it is cached once it is complete
+            match stream.and_then(|stream| stream_answer(stream, true)) {
+                Some((response, new_context)) => {
+                    cache.add_response(Entry {
+                        session,
+                        model: model.clone(),
+                        prompt: prompt.clone(),
+                        response: response.clone(),
+                        context: new_context,
+                        embedding,
+                        asked_in: Some(asked_in),
+                        options: settings,
+                        seed: Some(seed),
+                        chat: api == Api::Chat,
+                    });
+                    Some(Answer { text: response, cached: None })
                 }
                 None => {
+                    log::warn("model", "no answer from the model", &[("model", &model)]);
                     None
                 }
             }
         }
     }
+
+    // List the history of a session so the host can show or export it
+    fn history(file_path: String, session: String) -> Option<Vec<Exchange>> {
+        Some(FileCache::open(&file_path)?.history(&session))
+    }
+
+    // Asks the prompt of one exchange of a session again, with the options and
+    // seed its answer was generated with, after the conversation as it was recorded
+    fn replay(file_path: String, session: String, index: u32) -> Option<Replayed> {
+        let cache = FileCache::open(&file_path)?;
+        let mut earlier = cache.session_entries(&session);
+        let index = index as usize;
+        if index >= earlier.len() {
+            return None;
+        }
+        let entry = earlier.remove(index);
+        earlier.truncate(index);
+
+        // The prompt continues from the recorded answers, not the replayed ones
+        let model = entry.model.to_lowercase();
+        let context = earlier
+            .iter()
+            .rev()
+            .find(|earlier| earlier.model.to_lowercase() == model)
+            .map(|earlier| earlier.context.as_slice())
+            .unwrap_or_default();
+        let messages: Vec<Message> = earlier
+            .iter()
+            .flat_map(|earlier| {
+                [
+                    Message { role: Role::User, content: earlier.prompt.clone() },
+                    Message { role: Role::Assistant, content: earlier.response.clone() },
+                ]
+            })
+            .collect();
+
+        let api = if entry.chat { Api::Chat } else { Api::Generate };
+        let seed = entry.seed.or(entry.options.seed);
+        let options = entry.options.options(seed);
+        let answer = start_answer(api, &entry.model, &entry.prompt, context, &messages, &options)
+            .and_then(|stream| stream_answer(stream, false))
+            .map(|(response, _)| response);
+        log::info("history", "exchange replayed", &[("session", &session), ("index", &index), ("answered", &answer.is_some())]);
+        Some(Replayed {
+            model: entry.model,
+            prompt: entry.prompt,
+            seed,
+            cached: entry.response,
+            replayed: answer,
+        })
+    }
+
+    fn create_session(file_path: String, name: String) -> Option<String> {
+        let id = FileCache::open(&file_path)?.create_session(&name);
+        log::info("history", "session created", &[("session", &id), ("name", &name)]);
+        Some(id)
+    }
+
+    fn list_sessions(file_path: String) -> Option<Vec<SessionInfo>> {
+        Some(FileCache::open(&file_path)?.sessions())
+    }
+
+    fn rename_session(file_path: String, id: String, name: String) -> bool {
+        FileCache::open(&file_path).is_some_and(|cache| cache.rename_session(&id, &name))
+    }
+
+    fn delete_session(file_path: String, id: String) -> bool {
+        let deleted = FileCache::open(&file_path).is_some_and(|cache| cache.delete_session(&id));
+        if deleted {
+            log::info("history", "session deleted", &[("session", &id)]);
+        }
+        deleted
+    }
+
+    // Forget the whole history
+    fn clear(file_path: String) -> bool {
+        match FileCache::open(&file_path) {
+            Some(cache) => {
+                cache.clear();
+                log::info("history", "history cleared", &[("store", &file_path)]);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
-export!(MyHost);
+export!(MyHost with_types_in bindings);
 
@@ -0,0 +1,84 @@
+use host_common::config::GuestLimits;
+use host_common::provider::{GenerationOptions, ProviderConfig};
+use serde::Deserialize;
+
+// Settings the host applies to every guest it runs.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HostConfig {
+    pub limits: GuestLimits,
+    pub matching: MatchConfig,
+    pub model: ModelConfig,
+}
+
+// How the model is asked.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct ModelConfig {
+    // How the conversation reaches models served by ollama. Other providers
+    // are always sent the messages.
+    pub api: ApiKind,
+    pub options: GenerationOptions,
+    // Where models are served. The first provider listing a model serves it,
+    // and ollama on localhost serves the models none of them list.
+    pub providers: Vec<ProviderConfig>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKind {
+    // /api/generate, continuing from the context tokens of the last answer.
+    #[default]
+    Generate,
+    // /api/chat, with the session's earlier prompts and answers as messages.
+    Chat,
+}
+
+// How the guest matches a prompt against the history.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MatchConfig {
+    pub strategy: Strategy,
+    // Lowest similarity, from 0 to 1, a fuzzy or semantic match is accepted at.
+    pub threshold: f32,
+    // Only reuse answers given earlier in the same conversation, so that a
+    // follow-up like "and in Italian?" is never answered from another one.
+    pub in_context: bool,
+    // Where the embeddings for semantic matching come from.
+    pub embedder: EmbedderKind,
+    pub embedding_model: String,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            strategy: Strategy::Normalized,
+            threshold: 0.9,
+            in_context: false,
+            embedder: EmbedderKind::Ollama,
+            embedding_model: "nomic-embed-text".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    // The prompt as typed.
+    Exact,
+    // Case and runs of whitespace are ignored.
+    Normalized,
+    // The most similar earlier prompt above the threshold.
+    Fuzzy,
+    // The earlier prompt closest in meaning above the threshold.
+    Semantic,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmbedderKind {
+    // Ollama's /api/embeddings with the embedding model.
+    Ollama,
+    // Word counts hashed into a vector, for tests without a model.
+    Stub,
+}
@@ -0,0 +1,107 @@
+use serde_json::{json, Value};
+
+use crate::config::{EmbedderKind, MatchConfig};
+
+const API_URL: &str = "http://localhost:11434/api/embeddings";
+
+// Length of the vectors the stub makes.
+const STUB_DIMENSIONS: usize = 256;
+
+/// Makes the embeddings the guest matches prompts by.
+pub struct Embedder {
+    kind: EmbedderKind,
+    model: String,
+}
+
+impl Embedder {
+    pub fn new(config: &MatchConfig) -> Self {
+        Embedder {
+            kind: config.embedder,
+            model: config.embedding_model.clone(),
+        }
+    }
+
+    #[tracing::instrument(skip(self, text), fields(model = %self.model))]
+    pub async fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        match self.kind {
+            EmbedderKind::Ollama => self.ollama(text).await,
+            EmbedderKind::Stub => Some(stub(text)),
+        }
+    }
+
+    async fn ollama(&self, text: &str) -> Option<Vec<f32>> {
+        let payload = json!({
+            "model": &self.model,
+            "prompt": text,
+        });
+        let response = reqwest::Client::new().post(API_URL).json(&payload).send().await.ok()?;
+        if !response.status().is_success() {
+            tracing::warn!(status = response.status().as_u16(), "no embedding from the model");
+            return None;
+        }
+        let body: Value = response.json().await.ok()?;
+        let embedding = body["embedding"].as_array()?;
+        Some(embedding.iter().filter_map(Value::as_f64).map(|value| value as f32).collect())
+    }
+}
+
+// Counts the words of the text into a fixed number of slots by their hash.
+// Texts that share most of their words come out close, which is enough to
+// exercise semantic matching without a model.
+fn stub(text: &str) -> Vec<f32> {
+    let mut embedding = vec![0.0; STUB_DIMENSIONS];
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        embedding[fnv1a(word.to_lowercase().as_bytes()) as usize % STUB_DIMENSIONS] += 1.0;
+    }
+    embedding
+}
+
+// FNV-1a hash of the bytes. Unlike the standard library's hasher it is the
+// same on every build, so stored stub embeddings stay comparable.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET, |hash, byte| (hash ^ *byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guest_common::similarity::cosine_similarity;
+
+    // The default threshold of the matching section.
+    const THRESHOLD: f32 = 0.9;
+
+    // Scores two prompts the way the guest does.
+    fn score(a: &str, b: &str) -> f32 {
+        cosine_similarity(&stub(a), &stub(b))
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn stub_ignores_case_and_punctuation() {
+        assert_eq!(stub("What is the capital of France?"), stub("what is the CAPITAL of france"));
+    }
+
+    #[test]
+    fn similar_prompts_match() {
+        assert!(score("What is the capital of France?", "what is the capital city of France") >= THRESHOLD);
+        assert!(score("How do I reverse a list in Python?", "how do I reverse a Python list") >= THRESHOLD);
+    }
+
+    #[test]
+    fn unrelated_prompts_do_not_match() {
+        assert!(score("What is the capital of France?", "How do I bake sourdough bread?") < THRESHOLD);
+        assert!(score("How do I reverse a list in Python?", "Write a poem about the sea") < THRESHOLD);
+    }
+    #[test]
+    fn prompts_without_words_match_nothing() {
+        assert_eq!(score("?!", "What is the capital of France?"), 0.0);
+    }
+}
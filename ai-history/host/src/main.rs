@@ -1,77 +1,95 @@
-use std::{fs, error::Error};
-use wasmtime::{component::{ResourceTable, bindgen, Component, Linker}, *};
+use std::error::Error;
+use host_common::{keyvalue, limits, logging, precompile, provider, stream};
+use config::{ApiKind, HostConfig, MatchConfig, ModelConfig, Strategy};
+use provider::{GenerationOptions, Provider};
+use std::io::{self, Write};
+use wasmtime::{component::{Resource, ResourceTable, Linker}, *};
 use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
 
+mod config;
+mod embed;
+mod repl;
 
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use serde_json::json;
-use std::io::{self, BufRead, BufReader};
-
-bindgen!("chat" in "../guest/wit/witfile.wit");
+// The call functions bindgen generates take one argument per parameter,
+// which for `ask` is more than clippy allows.
+#[allow(clippy::too_many_arguments)]
+mod bindings {
+    wasmtime::component::bindgen!({
+        world: "chat",
+        path: "../guest/wit",
+        async: true,
+        tracing: true,
+        with: {
+            "wasi:keyvalue/store": host_common::wasi::keyvalue::store,
+            "wasi:keyvalue/atomics": host_common::wasi::keyvalue::atomics,
+            "alireza:logging/logging": host_common::alireza::logging::logging,
+            "host/model-stream": host_common::stream::ModelStream,
+        },
+    });
+}
+use bindings::*;
 
-struct HostComponent;
+// Host side of the `host` interface. Answers of the model are streamed to the
+// guest, which hands every token back to be printed as soon as it arrives.
+struct HostComponent {
+    table: ResourceTable,
+    // Whether the current call printed any tokens
+    streamed: bool,
+    embedder: embed::Embedder,
+    // Which provider serves each model
+    model: ModelConfig,
+}
 
 // Implementation of the host interface defined in the wit file.
 impl host::Host for HostComponent {
-    fn ask_model(&mut self, model: String, prompt: String, context: Vec<u64>) -> Option<String> {
-        let client = reqwest::blocking::Client::new();
-        let api_url = "http://localhost:11434/api/generate";
-        let mut payload = json!({
-            "model": &model,
-            "prompt": &prompt,
-            "stream": false,
-        });
-        if !context.is_empty() {
-            payload = json!({
-                "model": &model,
-                "prompt": &prompt,
-                "stream": false,
-                "context": &context,
-            });
-        }
+    async fn stream_model(&mut self, model: String, prompt: String, context: Vec<u64>, options: Options) -> Option<Resource<stream::ModelStream>> {
+        let provider = Provider::for_model(&self.model.providers, &model);
+        let stream = provider.generate(&model, &prompt, &context, &options.into()).await?;
+        self.table.push(stream).ok()
+    }
 
-        let response = client.post(api_url).json(&payload).send().ok()?;
-        let reader = BufReader::new(response);
-        let mut json_lines = String::new();
-        for line in reader.lines().flatten() {
-            if !line.trim().is_empty() {
-                json_lines.push_str(&line);
-                json_lines.push('\n');
-            }
-        }
-        if json_lines.trim().is_empty() {
-            // No response was returned by the model
-            None
-        } else {
-            Some(json_lines)
-        }
+    async fn stream_chat(&mut self, model: String, messages: Vec<host::Message>, options: Options) -> Option<Resource<stream::ModelStream>> {
+        let provider = Provider::for_model(&self.model.providers, &model);
+        let messages: Vec<_> = messages.into_iter().map(provider::Message::from).collect();
+        let stream = provider.chat(&model, &messages, &options.into()).await?;
+        self.table.push(stream).ok()
+    }
 
+    async fn print_token(&mut self, token: String) {
+        self.streamed = true;
+        print!("{}", token);
+        let _ = io::stdout().flush();
     }
 
-    fn write_to_file(&mut self, data: String, file_name: String) {
-        if let Ok(mut file) = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&file_name)
-    {
-        let _ = file.write_all(data.as_bytes());
+    async fn embed(&mut self, text: String) -> Option<Vec<f32>> {
+        self.embedder.embed(&text).await
     }
+}
+
+// The `types` interface only holds types, but the guest imports it all the same.
+impl alireza::aihistory::types::Host for HostComponent {}
+
+impl host::HostModelStream for HostComponent {
+    async fn next(&mut self, stream: Resource<stream::ModelStream>) -> Result<Option<host::Chunk>, String> {
+        let stream = self.table.get_mut(&stream).map_err(|err| err.to_string())?;
+        let piece = stream.next_piece().await?;
+        Ok(piece.map(|piece| host::Chunk { text: piece.text, context: piece.context }))
     }
 
-    fn read_from_file(&mut self, file_name: String) -> String {
-        match File::open(&file_name) {
-            Ok(mut file) => {
-                let mut contents = String::new();
-                if file.read_to_string(&mut contents).is_ok() {
-                    return contents;
-                } else {
-                    String::from("")
-                }
-            },
-            Err(_) => String::from(""),
-        }
+    async fn drop(&mut self, stream: Resource<stream::ModelStream>) -> wasmtime::Result<()> {
+        self.table.delete(stream)?;
+        Ok(())
+    }
+}
+
+impl From<host::Message> for provider::Message {
+    fn from(message: host::Message) -> Self {
+        let role = match message.role {
+            host::Role::System => provider::Role::System,
+            host::Role::User => provider::Role::User,
+            host::Role::Assistant => provider::Role::Assistant,
+        };
+        provider::Message { role, content: message.content }
     }
 }
 
@@ -79,6 +97,9 @@ struct MyState {
     ctx: WasiCtx,
     table: ResourceTable,
     host: HostComponent,
+    keyvalue: keyvalue::KeyValue,
+    logger: logging::GuestLogger,
+    limiter: limits::GuestLimiter,
 }
 impl IoView for MyState {
     fn table(&mut self) -> &mut ResourceTable { &mut self.table }
@@ -88,51 +109,264 @@ impl WasiView for MyState {
     fn ctx(&mut self) -> &mut WasiCtx { &mut self.ctx }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let engine = Engine::new(Config::new().wasm_component_model(true))?;
-    let bytes = fs::read("../guest/target/wasm32-wasip2/release/guest_cache.wasm")?;
-    let component = Component::new(&engine, &bytes)?;
-    let wasi_ctx = WasiCtxBuilder::new().inherit_stdio().build();
-
-    let mut store = wasmtime::Store::new(&engine,
-         MyState {
-            ctx: wasi_ctx,
+impl MyState {
+    fn new(ctx: WasiCtx, config: &HostConfig) -> Self {
+        MyState {
+            ctx,
             table: ResourceTable::new(),
-            host: HostComponent {},
-         });
-    let mut linker = Linker::new(&engine);
-    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
-    host::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
+            host: HostComponent {
+                table: ResourceTable::new(),
+                streamed: false,
+                embedder: embed::Embedder::new(&config.matching),
+                model: config.model.clone(),
+            },
+            keyvalue: keyvalue::KeyValue::new("."),
+            logger: logging::GuestLogger,
+            limiter: limits::GuestLimiter::new(&config.limits),
+        }
+    }
+}
+
+// An instance of the chat guest. A call that traps, for example on running
+// out of fuel, leaves the instance unusable, so it is replaced with a fresh one
+// and the host can carry on with the next call.
+struct ChatGuest {
+    pre: ChatPre<MyState>,
+    config: HostConfig,
+    store: Store<MyState>,
+    functions: Chat,
+}
+
+impl ChatGuest {
+    async fn new(pre: ChatPre<MyState>, config: HostConfig) -> anyhow::Result<Self> {
+        let (store, functions) = Self::instantiate(&pre, &config).await?;
+        Ok(ChatGuest { pre, config, store, functions })
+    }
+
+    async fn instantiate(pre: &ChatPre<MyState>, config: &HostConfig) -> anyhow::Result<(Store<MyState>, Chat)> {
+        let wasi_ctx = WasiCtxBuilder::new().inherit_stdio().build();
+        let mut store = Store::new(pre.engine(), MyState::new(wasi_ctx, config));
+        store.limiter(|state| &mut state.limiter);
+        limits::arm(&mut store, &config.limits)?;
+        let functions = pre.instantiate_async(&mut store).await?;
+        Ok((store, functions))
+    }
 
-    let functions = Chat::instantiate(&mut store, &component, &linker)?;
+    #[tracing::instrument(skip(self, prompt))]
+    async fn ask(&mut self, file_path: &str, session: &str, model: &str, prompt: &str) -> anyhow::Result<Option<Answer>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        self.store.data_mut().host.streamed = false;
+        let lookup = Lookup::from(&self.config.matching);
+        // Only ollama continues from context tokens, other providers take the messages
+        let api = match self.config.model.api {
+            ApiKind::Generate if Provider::for_model(&self.config.model.providers, model).takes_context() => Api::Generate,
+            _ => Api::Chat,
+        };
+        let options = Options::from(&self.config.model.options);
+        let result = self.functions.call_ask(&mut self.store, file_path, session, model, prompt, lookup, api, &options).await;
+        self.recover(result).await
+    }
 
+    async fn history(&mut self, file_path: &str, session: &str) -> anyhow::Result<Option<Vec<Exchange>>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_history(&mut self.store, file_path, session).await;
+        self.recover(result).await
+    }
+
+    async fn replay(&mut self, file_path: &str, session: &str, index: u32) -> anyhow::Result<Option<Replayed>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_replay(&mut self.store, file_path, session, index).await;
+        self.recover(result).await
+    }
 
+    async fn create_session(&mut self, file_path: &str, name: &str) -> anyhow::Result<Option<String>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_create_session(&mut self.store, file_path, name).await;
+        self.recover(result).await
+    }
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let mut handle = stdin.lock();
+    async fn list_sessions(&mut self, file_path: &str) -> anyhow::Result<Option<Vec<SessionInfo>>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_list_sessions(&mut self.store, file_path).await;
+        self.recover(result).await
+    }
 
-    print!("Enter Model: ");
-    stdout.flush().unwrap();
+    async fn rename_session(&mut self, file_path: &str, id: &str, name: &str) -> anyhow::Result<bool> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_rename_session(&mut self.store, file_path, id, name).await;
+        self.recover(result).await
+    }
 
-    let mut model = String::new();
-    let bytes_read = handle.read_line(&mut model).unwrap();
-    if bytes_read == 0 {
-        println!("Exiting...");
+    async fn delete_session(&mut self, file_path: &str, id: &str) -> anyhow::Result<bool> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_delete_session(&mut self.store, file_path, id).await;
+        self.recover(result).await
     }
 
-    print!(">>> ");
-    stdout.flush().unwrap();
-    let mut line = String::new();
-    let bytes_read = handle.read_line(&mut line).unwrap();
-    if bytes_read == 0 {
-        println!("Exiting...");
+    async fn clear(&mut self, file_path: &str) -> anyhow::Result<bool> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_clear(&mut self.store, file_path).await;
+        self.recover(result).await
     }
-    let result1 = functions.call_ask(&mut store, "./data.json", &model.trim(), &line);
-    match &result1 {
-        Ok(value) => println!("{:?}", value.as_ref().unwrap()),
-        Err(err) => println!("{:?}", err),
+
+    // Whether the last answer was printed token by token while it was streamed.
+    fn streamed(&self) -> bool {
+        self.store.data().host.streamed
     }
 
+    // Replaces the instance after a failed call, then hands the result back.
+    async fn recover<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        if result.is_err() {
+            (self.store, self.functions) = Self::instantiate(&self.pre, &self.config).await?;
+        }
+        result
+    }
+}
+
+impl From<&MatchConfig> for Lookup {
+    fn from(config: &MatchConfig) -> Self {
+        let strategy = match config.strategy {
+            Strategy::Exact => MatchStrategy::Exact,
+            Strategy::Normalized => MatchStrategy::Normalized,
+            Strategy::Fuzzy => MatchStrategy::Fuzzy(config.threshold),
+            Strategy::Semantic => MatchStrategy::Semantic(config.threshold),
+        };
+        Lookup { strategy, in_context: config.in_context }
+    }
+}
+
+impl From<&GenerationOptions> for Options {
+    fn from(options: &GenerationOptions) -> Self {
+        Options {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+            max_tokens: options.max_tokens,
+            system: options.system.clone(),
+            stop: options.stop.clone(),
+        }
+    }
+}
+
+impl From<Options> for GenerationOptions {
+    fn from(options: Options) -> Self {
+        GenerationOptions {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+            max_tokens: options.max_tokens,
+            system: options.system,
+            stop: options.stop,
+        }
+    }
+}
+
+// Bucket the conversation is kept in.
+const HISTORY_FILE: &str = "./data.json";
+
+// Path of the compiled guest component, relative to the host crate.
+const GUEST_PATH: &str = "../guest/target/wasm32-wasip2/release/guest_cache.wasm";
+
+// Builds the engine guests run on. Compiled components are only reusable by
+// engines built the same way.
+fn build_engine(config: &HostConfig) -> anyhow::Result<Engine> {
+    let mut engine_config = Config::new();
+    engine_config.wasm_component_model(true).async_support(true);
+    limits::configure(&mut engine_config, &config.limits);
+    let engine = Engine::new(&engine_config)?;
+    if config.limits.timeout_ms.is_some() {
+        limits::start_epoch_ticker(&engine);
+    }
+    Ok(engine)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // `host precompile [wasm]` compiles the guest into the compile cache and exits,
+    // `host replay <session>` asks the prompts of a session again and reports the
+    // answers that changed, and `host [model]` starts the chat, asking for the
+    // model when none is given.
+    let args: Vec<String> = std::env::args().collect();
+    logging::init();
+    let config = host_common::config::load::<HostConfig>()?;
+    match args.get(1).map(String::as_str) {
+        Some("precompile") => {
+            let wasm_path = args.get(2).map(String::as_str).unwrap_or(GUEST_PATH);
+            let compiled = precompile::precompile(&build_engine(&config)?, wasm_path)?;
+            println!("Compiled {} to {}", wasm_path, compiled.display());
+            Ok(())
+        }
+        Some("replay") => {
+            let Some(session) = args.get(2) else {
+                return Err("expected replay <session>".into());
+            };
+            tokio::runtime::Runtime::new()?.block_on(replay(config, session))
+        }
+        _ => {
+            let model = args.get(1).cloned();
+            tokio::runtime::Runtime::new()?.block_on(run(config, model))
+        }
+    }
+}
+
+// Guest calls run on the tokio runtime, so the host only waits on the model
+// without holding on to a thread.
+async fn run(config: HostConfig, model: Option<String>) -> Result<(), Box<dyn Error>> {
+    let mut guest = start_guest(config).await?;
+    repl::run(&mut guest, model).await?;
     Ok(())
 }
+
+// Asks every prompt of a session again with the seed and options of its answer,
+// and reports which answers differ from the ones in the history.
+async fn replay(config: HostConfig, session: &str) -> Result<(), Box<dyn Error>> {
+    let mut guest = start_guest(config).await?;
+    let Some(exchanges) = guest.history(HISTORY_FILE, session).await? else {
+        return Err(format!("could not open the history store {}", HISTORY_FILE).into());
+    };
+    let mut differ = 0;
+    // One call per exchange, so every answer gets the fuel and time of a call
+    for (i, exchange) in exchanges.iter().enumerate() {
+        let replayed = match guest.replay(HISTORY_FILE, session, i as u32).await {
+            Ok(Some(replayed)) => replayed,
+            Ok(None) => return Err(format!("the session {} changed while it was replayed", session).into()),
+            Err(err) => {
+                differ += 1;
+                println!("{}. [failed] {} {:?}: {}", i + 1, exchange.model, exchange.prompt, limits::describe(&err));
+                continue;
+            }
+        };
+        let seed = replayed.seed.map_or("none".to_string(), |seed| seed.to_string());
+        let outcome = match &replayed.replayed {
+            Some(answer) if *answer == replayed.cached => "same",
+            Some(_) => "differs",
+            None => "no answer",
+        };
+        println!("{}. [{}] {} seed={} {:?}", i + 1, outcome, replayed.model, seed, replayed.prompt);
+        if outcome != "same" {
+            differ += 1;
+            println!("--- cached\n{}", replayed.cached.trim());
+            if let Some(answer) = &replayed.replayed {
+                println!("--- replayed\n{}", answer.trim());
+            }
+        }
+    }
+    println!("{} of {} answers differ", differ, exchanges.len());
+    Ok(())
+}
+
+// Compiles the guest, links its imports and starts an instance.
+async fn start_guest(config: HostConfig) -> anyhow::Result<ChatGuest> {
+    let engine = build_engine(&config)?;
+    let component = precompile::load(&engine, GUEST_PATH)?;
+
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::add_to_linker_async(&mut linker)?;
+    host::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
+    alireza::aihistory::types::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
+    host_common::wasi::keyvalue::store::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::wasi::keyvalue::atomics::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::alireza::logging::logging::add_to_linker(&mut linker, |state: &mut MyState| &mut state.logger)?;
+
+    let pre = ChatPre::new(linker.instantiate_pre(&component)?)?;
+    ChatGuest::new(pre, config).await
+}
@@ -0,0 +1,255 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use serde_json::json;
+
+use crate::{limits, ChatGuest, HISTORY_FILE};
+
+// Session prompts go to until another one is picked.
+const DEFAULT_SESSION: &str = "default";
+
+// Where `/export` writes the history when no path is given.
+const DEFAULT_EXPORT: &str = "history.json";
+
+// Opens and closes a prompt that spans several lines, as in the ollama REPL.
+const MULTILINE: &str = "\"\"\"";
+
+const HELP: &str = "\
+/model [name]    show or switch the model
+/session [name]  show the session, or switch to another one, starting it if needed
+/sessions        list the sessions
+/rename <name>   rename the session
+/delete [name]   delete a session and its history, the current one by default
+/history         show the history of the session
+/clear           forget every session and its history
+/export [path]   write the history of the session to a JSON file
+/quit            leave, as does end of input
+Start and end a prompt with \"\"\" to write it over several lines.";
+
+/// Runs the chat loop until /quit or the end of input. Every prompt goes
+/// through the guest, so repeated prompts are answered from the history and
+/// new ones are asked with the context of the session so far.
+pub async fn run(guest: &mut ChatGuest, model: Option<String>) -> anyhow::Result<()> {
+    let mut model = match model {
+        Some(model) => model,
+        None => match read_line("Enter Model: ")? {
+            Some(model) if !model.trim().is_empty() => model.trim().to_string(),
+            _ => return Ok(()),
+        },
+    };
+    let mut session = DEFAULT_SESSION.to_string();
+    println!("Chatting with {} in session {}, /help lists the commands.", model, session);
+
+    loop {
+        let Some(line) = read_line(">>> ")? else {
+            println!();
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(command) = line.strip_prefix('/') {
+            let (name, arg) = match command.split_once(char::is_whitespace) {
+                Some((name, arg)) => (name, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+                None => (command, None),
+            };
+            match name {
+                "model" => match arg {
+                    Some(name) => {
+                        model = name.to_string();
+                        println!("Switched to {}", model);
+                    }
+                    None => println!("{}", model),
+                },
+                "session" => match arg {
+                    Some(wanted) => {
+                        if let Err(err) = switch_session(guest, &mut session, wanted).await {
+                            println!("Switching sessions failed: {}", limits::describe(&err));
+                        }
+                    }
+                    None => println!("{}", session),
+                },
+                "sessions" => match guest.list_sessions(HISTORY_FILE).await {
+                    Ok(Some(sessions)) if sessions.is_empty() => println!("No sessions yet"),
+                    Ok(Some(sessions)) => {
+                        for info in sessions {
+                            let marker = if info.id == session { '*' } else { ' ' };
+                            println!("{} {:<8} {} ({} exchanges)", marker, info.id, info.name, info.exchanges);
+                        }
+                    }
+                    Ok(None) => println!("The history store could not be opened"),
+                    Err(err) => println!("The call failed: {}", limits::describe(&err)),
+                },
+                "rename" => match arg {
+                    Some(name) => match guest.rename_session(HISTORY_FILE, &session, name).await {
+                        Ok(true) => println!("Session {} renamed to {}", session, name),
+                        Ok(false) => println!("Session {} has not been started yet", session),
+                        Err(err) => println!("The call failed: {}", limits::describe(&err)),
+                    },
+                    None => println!("Usage: /rename <name>"),
+                },
+                "delete" => {
+                    if let Err(err) = delete_session(guest, &mut session, arg).await {
+                        println!("Deleting the session failed: {}", limits::describe(&err));
+                    }
+                }
+                "history" => match guest.history(HISTORY_FILE, &session).await {
+                    Ok(Some(exchanges)) if exchanges.is_empty() => println!("No history in session {}", session),
+                    Ok(Some(exchanges)) => {
+                        for exchange in exchanges {
+                            println!(">>> {}\n{}\n", exchange.prompt, exchange.response);
+                        }
+                    }
+                    Ok(None) => println!("The history store could not be opened"),
+                    Err(err) => println!("The call failed: {}", limits::describe(&err)),
+                },
+                "clear" => match guest.clear(HISTORY_FILE).await {
+                    Ok(true) => println!("History cleared"),
+                    Ok(false) => println!("The history store could not be opened"),
+                    Err(err) => println!("The call failed: {}", limits::describe(&err)),
+                },
+                "export" => {
+                    let path = arg.unwrap_or(DEFAULT_EXPORT);
+                    match export(guest, &session, path).await {
+                        Ok(count) => println!("Wrote {} exchanges to {}", count, path),
+                        Err(err) => println!("Export failed: {}", limits::describe(&err)),
+                    }
+                }
+                "quit" | "exit" | "bye" => break,
+                "help" | "?" => println!("{}", HELP),
+                _ => println!("Unknown command /{}, /help lists the commands", name),
+            }
+            continue;
+        }
+
+        let prompt = if let Some(first) = line.strip_prefix(MULTILINE) {
+            match read_multiline(first)? {
+                Some(prompt) => prompt,
+                None => break,
+            }
+        } else {
+            line.to_string()
+        };
+        match guest.ask(HISTORY_FILE, &session, &model, &prompt).await {
+            Ok(Some(answer)) => match answer.cached {
+                Some(found) => println!(
+                    "{}\n[from history, asked as \"{}\", similarity {:.2}]\n",
+                    answer.text.trim(),
+                    found.prompt,
+                    found.score
+                ),
+                // A streamed answer is already on the screen
+                None if guest.streamed() => println!("\n"),
+                None => println!("{}\n", answer.text.trim()),
+            },
+            Ok(None) => println!("No answer from the model"),
+            Err(err) => println!("The call failed: {}", limits::describe(&err)),
+        }
+    }
+    Ok(())
+}
+
+// Prints the prompt and reads one line, None at the end of input.
+fn read_line(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+// Reads the rest of a prompt opened with """ until the line that closes it.
+// The end of input gives None, dropping the unfinished prompt.
+fn read_multiline(first: &str) -> io::Result<Option<String>> {
+    if let Some(prompt) = first.strip_suffix(MULTILINE) {
+        return Ok(Some(prompt.to_string()));
+    }
+    let mut lines = vec![first.to_string()];
+    loop {
+        let Some(line) = read_line("... ")? else {
+            return Ok(None);
+        };
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(last) = line.strip_suffix(MULTILINE) {
+            lines.push(last.to_string());
+            return Ok(Some(lines.join("\n").trim().to_string()));
+        }
+        lines.push(line.to_string());
+    }
+}
+
+// The id of the session with the given id or, failing that, name.
+async fn find_session(guest: &mut ChatGuest, wanted: &str) -> anyhow::Result<Option<String>> {
+    let Some(sessions) = guest.list_sessions(HISTORY_FILE).await? else {
+        anyhow::bail!("the history store could not be opened");
+    };
+    let found = sessions
+        .iter()
+        .find(|info| info.id == wanted)
+        .or_else(|| sessions.iter().find(|info| info.name == wanted));
+    Ok(found.map(|info| info.id.clone()))
+}
+
+// Switches to the session with the given id or name, starting a new one
+// under that name when there is none.
+async fn switch_session(guest: &mut ChatGuest, session: &mut String, wanted: &str) -> anyhow::Result<()> {
+    if let Some(id) = find_session(guest, wanted).await? {
+        println!("Switched to session {}", id);
+        *session = id;
+        return Ok(());
+    }
+    let Some(id) = guest.create_session(HISTORY_FILE, wanted).await? else {
+        anyhow::bail!("the history store could not be opened");
+    };
+    println!("Started session {} as {}", wanted, id);
+    *session = id;
+    Ok(())
+}
+
+// Deletes the named session, or the current one. Deleting the current session
+// goes back to the default one.
+async fn delete_session(guest: &mut ChatGuest, session: &mut String, wanted: Option<&str>) -> anyhow::Result<()> {
+    let id = match wanted {
+        Some(wanted) => match find_session(guest, wanted).await? {
+            Some(id) => id,
+            None => {
+                println!("No session {}", wanted);
+                return Ok(());
+            }
+        },
+        None => session.clone(),
+    };
+    if !guest.delete_session(HISTORY_FILE, &id).await? {
+        println!("Session {} has not been started yet", id);
+        return Ok(());
+    }
+    println!("Deleted session {}", id);
+    if id == *session {
+        *session = DEFAULT_SESSION.to_string();
+        println!("Back in session {}", session);
+    }
+    Ok(())
+}
+
+// Writes the history of the session to a JSON file and returns the number of exchanges.
+async fn export(guest: &mut ChatGuest, session: &str, path: &str) -> anyhow::Result<usize> {
+    let Some(exchanges) = guest.history(HISTORY_FILE, session).await? else {
+        anyhow::bail!("the history store could not be opened");
+    };
+    let exchanges: Vec<_> = exchanges
+        .into_iter()
+        .map(|exchange| {
+            json!({
+                "model": exchange.model,
+                "prompt": exchange.prompt,
+                "response": exchange.response,
+            })
+        })
+        .collect();
+    fs::write(path, serde_json::to_string_pretty(&exchanges)?)?;
+    Ok(exchanges.len())
+}
@@ -0,0 +1,127 @@
+use std::io::Read;
+
+use ::wasi::http::outgoing_handler;
+use ::wasi::http::types::{
+    ErrorCode, Fields, IncomingBody, IncomingResponse, Method, OutgoingRequest, Scheme,
+};
+use url::Url;
+
+/// A response received through wasi:http, with its body fully read.
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// Makes a response without fetching it, for tests of the guests.
+    pub fn new(status: u16, headers: &[(&str, &str)], body: &str) -> Self {
+        Response {
+            status,
+            body: body.to_string(),
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    /// Returns the first value of a header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Number of seconds the response may be cached for, taken from the
+    /// `max-age` directive of the Cache-Control header.
+    pub fn max_age(&self) -> Option<u64> {
+        self.header("cache-control")?
+            .split(',')
+            .find_map(|directive| directive.trim().strip_prefix("max-age="))
+            .and_then(|seconds| seconds.trim().parse().ok())
+    }
+
+    /// Whether the origin asked for the response not to be stored.
+    pub fn no_store(&self) -> bool {
+        self.header("cache-control").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+        })
+    }
+}
+
+/// Sends a GET request for `url` with the given headers through the host's
+/// wasi:http outgoing handler and waits for the whole response.
+pub fn get(url: &str, headers: &[(&str, &str)]) -> Result<Response, ErrorCode> {
+    let url = Url::parse(url).map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+
+    let headers: Vec<(String, Vec<u8>)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+        .collect();
+    let headers = Fields::from_list(&headers).map_err(|err| ErrorCode::InternalError(Some(format!("{:?}", err))))?;
+    let request = OutgoingRequest::new(headers);
+    request
+        .set_method(&Method::Get)
+        .map_err(|_| ErrorCode::HttpRequestMethodInvalid)?;
+    let scheme = match url.scheme() {
+        "http" => Scheme::Http,
+        "https" => Scheme::Https,
+        other => Scheme::Other(other.to_string()),
+    };
+    request
+        .set_scheme(Some(&scheme))
+        .map_err(|_| ErrorCode::HttpProtocolError)?;
+    let host = url.host_str().ok_or(ErrorCode::HttpRequestUriInvalid)?;
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    request
+        .set_authority(Some(&authority))
+        .map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    request
+        .set_path_with_query(Some(&path))
+        .map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+
+    let future = outgoing_handler::handle(request, None)?;
+    let response = loop {
+        match future.get() {
+            Some(result) => break result.map_err(|_| ErrorCode::InternalError(None))??,
+            None => future.subscribe().block(),
+        }
+    };
+    read_response(response)
+}
+
+// Collects the status, headers and body of a response.
+fn read_response(response: IncomingResponse) -> Result<Response, ErrorCode> {
+    let status = response.status();
+    let headers = response
+        .headers()
+        .entries()
+        .into_iter()
+        .map(|(name, value)| (name, String::from_utf8_lossy(&value).into_owned()))
+        .collect();
+
+    let body = response.consume().map_err(|_| ErrorCode::InternalError(None))?;
+    let mut bytes = Vec::new();
+    {
+        let mut stream = body.stream().map_err(|_| ErrorCode::InternalError(None))?;
+        stream
+            .read_to_end(&mut bytes)
+            .map_err(|err| ErrorCode::InternalError(Some(err.to_string())))?;
+    }
+    let _ = IncomingBody::finish(body);
+
+    Ok(Response {
+        status,
+        body: String::from_utf8_lossy(&bytes).into_owned(),
+        headers,
+    })
+}
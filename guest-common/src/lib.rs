@@ -0,0 +1,8 @@
+// Code the guest components share: fetching through wasi:http, logging
+// through the host and comparing embeddings. Built for wasm32-wasip2 together with the guest that depends on
+// it, and natively for the tests of the hosts that check what guests compute.
+
+pub mod fetch;
+#[cfg(feature = "log")]
+pub mod log;
+pub mod similarity;
@@ -0,0 +1,32 @@
+use std::fmt::Display;
+
+use bindings::alireza::logging::logging::{self, Field, Level};
+
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit/logging",
+        world: "alireza:logging/imports",
+    });
+}
+
+// Sends a log record to the host. Fields are passed as key-value pairs so the
+// host can keep them apart from the message.
+fn log(level: Level, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    let fields: Vec<Field> = fields
+        .iter()
+        .map(|(key, value)| Field { key: key.to_string(), value: value.to_string() })
+        .collect();
+    logging::log(level, target, message, &fields);
+}
+
+pub fn info(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Info, target, message, fields);
+}
+
+pub fn warn(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Warn, target, message, fields);
+}
+
+pub fn error(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    log(Level::Error, target, message, fields);
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use wasmtime_wasi::{HostWallClock, WasiCtxBuilder};
+
+// The mock clock of this process, when MOCK_CLOCK sets one.
+static MOCK: OnceLock<MockClock> = OnceLock::new();
+
+/// A wall clock that only moves when the host moves it, so that runs which
+/// depend on time, like cache expiry, give the same result every time.
+#[derive(Clone)]
+pub struct MockClock {
+    seconds: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    fn new(start: u64) -> Self {
+        MockClock { seconds: Arc::new(AtomicU64::new(start)) }
+    }
+
+    fn advance(&self, seconds: u64) {
+        self.seconds.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl HostWallClock for MockClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_secs(self.seconds.load(Ordering::Relaxed))
+    }
+}
+
+/// Starts the mock clock at the time in MOCK_CLOCK, in seconds since the
+/// epoch. Without it guests read the real time.
+pub fn init() -> Result<()> {
+    if let Ok(start) = std::env::var("MOCK_CLOCK") {
+        let start = start.parse().context("MOCK_CLOCK must be a number of seconds")?;
+        let _ = MOCK.set(MockClock::new(start));
+    }
+    Ok(())
+}
+
+/// Gives a guest the mock clock, if there is one.
+pub fn install(builder: &mut WasiCtxBuilder) -> &mut WasiCtxBuilder {
+    match MOCK.get() {
+        Some(clock) => builder.wall_clock(clock.clone()),
+        None => builder,
+    }
+}
+
+/// Moves the mock clock forward. The real clock is left alone.
+pub fn advance(seconds: u64) {
+    if let Some(clock) = MOCK.get() {
+        clock.advance(seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_only_moves_when_advanced() {
+        let clock = MockClock::new(1_700_000_000);
+        assert_eq!(clock.now(), Duration::from_secs(1_700_000_000));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.now(), Duration::from_secs(1_700_000_000));
+
+        clock.advance(61);
+        assert_eq!(clock.now(), Duration::from_secs(1_700_000_061));
+    }
+
+    #[test]
+    fn guests_given_the_clock_see_it_move() {
+        // Every guest gets a clone, and they all read the same time.
+        let clock = MockClock::new(0);
+        let given = clock.clone();
+        clock.advance(3600);
+        assert_eq!(given.now(), Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+// Default location of the host configuration, relative to the working
+// directory. The HOST_CONFIG environment variable overrides it.
+pub const CONFIG_PATH: &str = "host-config.json";
+
+/// Loads the configuration of a host.
+/// A missing file gives the defaults, which for the hosts that send requests
+/// deny all network access.
+pub fn load<T: DeserializeOwned + Default>() -> Result<T> {
+    let path = std::env::var("HOST_CONFIG").unwrap_or_else(|_| CONFIG_PATH.to_string());
+    if !Path::new(&path).exists() {
+        tracing::warn!(%path, "no host configuration, using the defaults");
+        return Ok(T::default());
+    }
+    let contents = fs::read_to_string(&path).with_context(|| format!("reading {}", path))?;
+    serde_json::from_str(&contents).with_context(|| format!("parsing {}", path))
+}
+
+// Limits on the guest's own execution. Each limit is off when left out.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct GuestLimits {
+    // Fuel given to every exported call, roughly one unit per instruction.
+    pub fuel: Option<u64>,
+    // Wall-clock deadline of every exported call.
+    pub timeout_ms: Option<u64>,
+    // Largest size a single linear memory of the guest may grow to.
+    pub max_memory_bytes: Option<usize>,
+    // Largest number of elements a single table of the guest may grow to.
+    pub max_table_elements: Option<usize>,
+    // Number of core instances and tables a guest store may create.
+    pub max_instances: Option<usize>,
+    pub max_tables: Option<usize>,
+}
+
+// Limits on every HTTP request made for a guest.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HttpLimits {
+    pub connect_timeout_ms: u64,
+    // Longest wait for the first byte of the response and between two chunks of its body.
+    pub read_timeout_ms: u64,
+    pub max_body_bytes: u64,
+    pub max_redirects: usize,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        HttpLimits {
+            connect_timeout_ms: 10_000,
+            read_timeout_ms: 30_000,
+            max_body_bytes: 10 * 1024 * 1024,
+            max_redirects: 5,
+        }
+    }
+}
+
+impl HttpLimits {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+}
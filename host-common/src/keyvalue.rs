@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use wasmtime::component::{Resource, ResourceTable};
+
+use crate::wasi::keyvalue::{atomics, store};
+
+// Number of keys returned by a single list-keys call.
+const PAGE_SIZE: usize = 256;
+
+// Host side of the wasi:keyvalue store and atomics interfaces.
+// Every bucket is a JSON file under `root`, named by the bucket identifier.
+pub struct KeyValue {
+    root: PathBuf,
+    table: ResourceTable,
+}
+
+// An open bucket. Only the path of its file is kept, every operation reads
+// and writes the file so that several handles to one bucket stay in sync.
+pub struct Bucket {
+    path: PathBuf,
+}
+
+// Values are kept as text when they are valid UTF-8 so the files stay
+// readable, and as a list of bytes otherwise.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredValue {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl StoredValue {
+    fn new(value: Vec<u8>) -> Self {
+        match String::from_utf8(value) {
+            Ok(text) => StoredValue::Text(text),
+            Err(err) => StoredValue::Bytes(err.into_bytes()),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            StoredValue::Text(text) => text.into_bytes(),
+            StoredValue::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+type Entries = BTreeMap<String, StoredValue>;
+
+impl KeyValue {
+    /// Creates a store whose buckets live in the given directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        KeyValue {
+            root: root.into(),
+            table: ResourceTable::new(),
+        }
+    }
+
+    fn bucket_path(&mut self, bucket: &Resource<Bucket>) -> Result<PathBuf, store::Error> {
+        self.table
+            .get(bucket)
+            .map(|bucket| bucket.path.clone())
+            .map_err(|err| store::Error::Other(err.to_string()))
+    }
+}
+
+// Guests may only name files below the root directory.
+fn is_allowed(identifier: &str) -> bool {
+    let path = Path::new(identifier);
+    !identifier.is_empty()
+        && path
+            .components()
+            .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+}
+
+// Reads the entries of a bucket, a missing file is an empty bucket.
+fn load(path: &Path) -> Result<Entries, store::Error> {
+    match fs::read(path) {
+        Ok(contents) if contents.is_empty() => Ok(Entries::new()),
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map_err(|err| store::Error::Other(format!("corrupt bucket {}: {}", path.display(), err))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Entries::new()),
+        Err(err) => Err(store::Error::Other(err.to_string())),
+    }
+}
+
+// Writes next to the bucket file first and then replaces it, so that a
+// concurrent load never reads a half written file.
+fn save(path: &Path, entries: &Entries) -> Result<(), store::Error> {
+    let json = serde_json::to_string(entries).map_err(|err| store::Error::Other(err.to_string()))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| store::Error::Other(err.to_string()))?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(".{}", std::process::id()));
+    fs::write(&partial, json).map_err(|err| store::Error::Other(err.to_string()))?;
+    fs::rename(&partial, path).map_err(|err| store::Error::Other(err.to_string()))
+}
+
+// The lock of a bucket file. Every operation on a bucket holds it, so that
+// concurrent calls in this process never lose each other's updates and reads
+// see each change whole. Guests keep what they change together under one key
+// and count with `increment`, since no lock is held between two calls.
+fn lock(path: &Path) -> Arc<Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+    locks.entry(path.to_path_buf()).or_default().clone()
+}
+
+impl store::Host for KeyValue {
+    async fn open(&mut self, identifier: String) -> Result<Resource<Bucket>, store::Error> {
+        if !is_allowed(&identifier) {
+            return Err(store::Error::AccessDenied);
+        }
+        let bucket = Bucket {
+            path: self.root.join(&identifier),
+        };
+        self.table
+            .push(bucket)
+            .map_err(|err| store::Error::Other(err.to_string()))
+    }
+}
+
+impl store::HostBucket for KeyValue {
+    async fn get(&mut self, bucket: Resource<Bucket>, key: String) -> Result<Option<Vec<u8>>, store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entries = load(&path)?;
+        Ok(entries.remove(&key).map(StoredValue::into_bytes))
+    }
+
+    async fn set(&mut self, bucket: Resource<Bucket>, key: String, value: Vec<u8>) -> Result<(), store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entries = load(&path)?;
+        entries.insert(key, StoredValue::new(value));
+        save(&path, &entries)
+    }
+
+    async fn delete(&mut self, bucket: Resource<Bucket>, key: String) -> Result<(), store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entries = load(&path)?;
+        if entries.remove(&key).is_some() {
+            save(&path, &entries)?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&mut self, bucket: Resource<Bucket>, key: String) -> Result<bool, store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(load(&path)?.contains_key(&key))
+    }
+
+    async fn list_keys(&mut self, bucket: Resource<Bucket>, cursor: Option<u64>) -> Result<store::KeyResponse, store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let entries = load(&path)?;
+        let start = cursor.unwrap_or(0) as usize;
+        let keys: Vec<String> = entries.into_keys().skip(start).take(PAGE_SIZE + 1).collect();
+        // Only hand out a cursor when there is at least one more key after this page
+        let cursor = (keys.len() > PAGE_SIZE).then(|| (start + PAGE_SIZE) as u64);
+        Ok(store::KeyResponse {
+            keys: keys.into_iter().take(PAGE_SIZE).collect(),
+            cursor,
+        })
+    }
+
+    async fn drop(&mut self, bucket: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.table.delete(bucket)?;
+        Ok(())
+    }
+}
+
+impl atomics::Host for KeyValue {
+    async fn increment(&mut self, bucket: Resource<Bucket>, key: String, delta: u64) -> Result<u64, store::Error> {
+        let path = self.bucket_path(&bucket)?;
+        let lock = lock(&path);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entries = load(&path)?;
+        // Counters are stored as decimal text, like any other readable value
+        let current = match entries.get(&key) {
+            Some(StoredValue::Text(text)) => text
+                .parse::<u64>()
+                .map_err(|_| store::Error::Other(format!("value of {} is not a number", key)))?,
+            Some(StoredValue::Bytes(_)) => {
+                return Err(store::Error::Other(format!("value of {} is not a number", key)));
+            }
+            None => 0,
+        };
+        let value = current.wrapping_add(delta);
+        entries.insert(key, StoredValue::Text(value.to_string()));
+        save(&path, &entries)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use store::{Host as _, HostBucket as _};
+
+    // Every call takes the bucket, so the tests hand out borrows of one handle.
+    fn borrow(bucket: &Resource<Bucket>) -> Resource<Bucket> {
+        Resource::new_borrow(bucket.rep())
+    }
+
+    #[test]
+    fn concurrent_sets_keep_every_key() {
+        let root = std::env::temp_dir().join(format!("keyvalue-test-{}", std::process::id()));
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let root = root.clone();
+                std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    runtime.block_on(async {
+                        let mut store = KeyValue::new(root);
+                        let bucket = store.open("bucket".to_string()).await.unwrap();
+                        for key in 0..10 {
+                            store.set(borrow(&bucket), format!("{}-{}", writer, key), vec![1]).await.unwrap();
+                        }
+                    })
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let keys = runtime.block_on(async {
+            let mut store = KeyValue::new(&root);
+            let bucket = store.open("bucket".to_string()).await.unwrap();
+            store.list_keys(borrow(&bucket), None).await.unwrap().keys
+        });
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(keys.len(), 80);
+    }
+}
@@ -0,0 +1,42 @@
+// Host code shared by the hosts of every example: the wasi:keyvalue store,
+// guest logging, the mock clock, the network policy, the guest limits and the
+// compile cache, and the model providers with the streams of their answers.
+
+pub mod clock;
+pub mod config;
+pub mod keyvalue;
+pub mod limits;
+pub mod logging;
+#[cfg(feature = "http")]
+pub mod outgoing;
+pub mod policy;
+pub mod precompile;
+#[cfg(feature = "stream")]
+pub mod provider;
+#[cfg(feature = "stream")]
+pub mod stream;
+
+// The wasi:keyvalue interfaces, generated once so that every host maps the
+// worlds of its guests onto them with `with` and shares the implementation in
+// keyvalue.rs.
+wasmtime::component::bindgen!({
+    world: "wasi:keyvalue/imports",
+    path: "wit/keyvalue",
+    async: true,
+    tracing: true,
+    with: {
+        "wasi:keyvalue/store/bucket": crate::keyvalue::Bucket,
+    },
+});
+
+// The logging interface, shared the same way. It lives in a module of its own
+// because every generated world brings a struct named after it.
+mod log_bindings {
+    wasmtime::component::bindgen!({
+        world: "alireza:logging/imports",
+        path: "wit/logging",
+        async: true,
+        tracing: true,
+    });
+}
+pub use log_bindings::alireza;
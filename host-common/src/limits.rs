@@ -0,0 +1,144 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use wasmtime::{
+    Config, Engine, ResourceLimiter, Store, Trap, DEFAULT_INSTANCE_LIMIT, DEFAULT_TABLE_LIMIT,
+};
+
+use crate::config::GuestLimits;
+
+// How often the epoch advances, which is the resolution of call timeouts.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+// Deadline of calls without a timeout, far enough that the ticker never
+// reaches it and small enough not to overflow when added to the current epoch.
+const NO_DEADLINE: u64 = u64::MAX / 2;
+
+/// Turns on the engine features needed by the configured limits.
+pub fn configure(config: &mut Config, limits: &GuestLimits) {
+    config.consume_fuel(limits.fuel.is_some());
+    config.epoch_interruption(limits.timeout_ms.is_some());
+}
+
+/// Turns on fuel metering and epoch interruption, for an engine shared by
+/// guests with limits of their own. Guests without a limit are armed with as
+/// much fuel and time as they like.
+pub fn configure_shared(config: &mut Config) {
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+}
+
+/// Starts the thread that advances the epoch of the engine, as long as the
+/// engine is alive. Only needed when calls have a timeout.
+pub fn start_epoch_ticker(engine: &Engine) {
+    let engine = engine.weak();
+    thread::spawn(move || {
+        while let Some(engine) = engine.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            thread::sleep(EPOCH_TICK);
+        }
+    });
+}
+
+/// Refuels the store and sets a new deadline, before every call into the guest.
+pub fn arm<T>(store: &mut Store<T>, limits: &GuestLimits) -> Result<()> {
+    match limits.fuel {
+        Some(fuel) => store.set_fuel(fuel)?,
+        // Reading the fuel only fails when the engine does not meter it
+        None if store.get_fuel().is_ok() => store.set_fuel(u64::MAX)?,
+        None => {}
+    }
+    let ticks = match limits.timeout_ms {
+        Some(timeout_ms) => timeout_ms.div_ceil(EPOCH_TICK.as_millis() as u64).max(1),
+        None => NO_DEADLINE,
+    };
+    store.set_epoch_deadline(ticks);
+    Ok(())
+}
+
+/// Limits the memories and tables of the guest in one store.
+/// Growing past a limit traps the guest with a `LimitExceeded` error, rather
+/// than failing the allocation, so the host can tell which limit was hit.
+pub struct GuestLimiter {
+    max_memory_bytes: Option<usize>,
+    max_table_elements: Option<usize>,
+    max_instances: usize,
+    max_tables: usize,
+}
+
+impl GuestLimiter {
+    pub fn new(limits: &GuestLimits) -> Self {
+        GuestLimiter {
+            max_memory_bytes: limits.max_memory_bytes,
+            max_table_elements: limits.max_table_elements,
+            max_instances: limits.max_instances.unwrap_or(DEFAULT_INSTANCE_LIMIT),
+            max_tables: limits.max_tables.unwrap_or(DEFAULT_TABLE_LIMIT),
+        }
+    }
+}
+
+impl ResourceLimiter for GuestLimiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        match self.max_memory_bytes {
+            Some(limit) if desired > limit => Err(LimitExceeded::Memory { current, desired, limit }.into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn table_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        match self.max_table_elements {
+            Some(limit) if desired > limit => Err(LimitExceeded::Table { current, desired, limit }.into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+    }
+
+    fn tables(&self) -> usize {
+        self.max_tables
+    }
+}
+
+// The limit a guest tried to grow past.
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Memory { current: usize, desired: usize, limit: usize },
+    Table { current: usize, desired: usize, limit: usize },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Memory { current, desired, limit } => write!(
+                f,
+                "the guest tried to grow its memory from {} to {} bytes, past the limit of {} bytes",
+                current, desired, limit
+            ),
+            LimitExceeded::Table { current, desired, limit } => write!(
+                f,
+                "the guest tried to grow a table from {} to {} elements, past the limit of {} elements",
+                current, desired, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Explains why a call into the guest failed.
+pub fn describe(err: &anyhow::Error) -> String {
+    if let Some(exceeded) = err.downcast_ref::<LimitExceeded>() {
+        return exceeded.to_string();
+    }
+    match err.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => "the guest ran out of fuel".to_string(),
+        Some(Trap::Interrupt) => "the guest exceeded its time limit".to_string(),
+        Some(trap) => format!("the guest trapped: {}", trap),
+        None => format!("{:#}", err),
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use crate::alireza::logging::logging::{self, Field};
+
+// Most verbose level written when LOG_LEVEL does not set one.
+const DEFAULT_LEVEL: Level = Level::INFO;
+
+/// Writes the log records of the host and its guests to stderr, as one JSON
+/// object per line. Closing a span writes a record with its duration, so every
+/// guest call and host function shows up with its timing.
+pub fn init() {
+    let level = std::env::var("LOG_LEVEL")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(DEFAULT_LEVEL);
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+// Host side of the logging interface. Guest records become tracing events
+// with the `guest` target, inside the span of the call that made them.
+pub struct GuestLogger;
+
+impl logging::Host for GuestLogger {
+    async fn log(&mut self, level: logging::Level, target: String, message: String, fields: Vec<Field>) {
+        let fields: BTreeMap<String, String> = fields.into_iter().map(|field| (field.key, field.value)).collect();
+        let fields = serde_json::to_string(&fields).unwrap_or_default();
+        // tracing needs the level of an event at compile time
+        macro_rules! guest_event {
+            ($level:expr) => {
+                tracing::event!(target: "guest", $level, guest_target = %target, fields = %fields, "{}", message)
+            };
+        }
+        match level {
+            logging::Level::Trace => guest_event!(Level::TRACE),
+            logging::Level::Debug => guest_event!(Level::DEBUG),
+            logging::Level::Info => guest_event!(Level::INFO),
+            logging::Level::Warn => guest_event!(Level::WARN),
+            logging::Level::Error => guest_event!(Level::ERROR),
+        }
+    }
+}
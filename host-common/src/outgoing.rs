@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::header::{HeaderName, AUTHORIZATION, CONTENT_LENGTH, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION};
+use hyper::{Method, Request, Uri};
+use tracing::Instrument;
+use url::Url;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::{HyperIncomingBody, HyperOutgoingBody};
+use wasmtime_wasi_http::types::{
+    default_send_request_handler, HostFutureIncomingResponse, IncomingResponse,
+    OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::HttpResult;
+
+use crate::config::HttpLimits;
+use crate::policy::NetworkPolicy;
+
+/// The parts of a host's configuration that the requests of its guests are
+/// sent under.
+pub trait OutgoingConfig: Send + Sync + 'static {
+    fn network(&self) -> &NetworkPolicy;
+    fn http(&self) -> &HttpLimits;
+}
+
+/// Checks an outgoing request of a guest against the network policy, and
+/// sends it within the limits when it is allowed. `observe` is given the time
+/// the request took, redirects included.
+pub fn send_request<C: OutgoingConfig>(
+    request: Request<HyperOutgoingBody>,
+    config: OutgoingRequestConfig,
+    host: Arc<C>,
+    observe: fn(Duration),
+) -> HttpResult<HostFutureIncomingResponse> {
+    let uri = request.uri();
+    let scheme = uri.scheme_str().unwrap_or(if config.use_tls { "https" } else { "http" });
+    let port = uri.port_u16().unwrap_or(if config.use_tls { 443 } else { 80 });
+    let allowed = host.network().check(scheme, uri.host().unwrap_or_default(), port, uri.path());
+    if let Err(reason) = allowed {
+        tracing::warn!(%uri, %reason, "denied guest request");
+        return Err(ErrorCode::HttpRequestDenied.into());
+    }
+    Ok(send(request, config, host, observe))
+}
+
+// Sends a request that already passed the network policy, applying the
+// timeouts, redirect and body size limits of the host configuration.
+// The guest may ask for shorter timeouts than the configured ones, but never
+// for longer ones.
+fn send<C: OutgoingConfig>(
+    request: Request<HyperOutgoingBody>,
+    mut config: OutgoingRequestConfig,
+    host: Arc<C>,
+    observe: fn(Duration),
+) -> HostFutureIncomingResponse {
+    let limits = host.http();
+    config.connect_timeout = config.connect_timeout.min(limits.connect_timeout());
+    config.first_byte_timeout = config.first_byte_timeout.min(limits.read_timeout());
+    config.between_bytes_timeout = config.between_bytes_timeout.min(limits.read_timeout());
+
+    let span = tracing::info_span!("outgoing_request", method = %request.method(), uri = %request.uri());
+    let handle = wasmtime_wasi::runtime::spawn(
+        async move {
+            let start = Instant::now();
+            let response = send_with_limits(request, config, host.network(), host.http()).await;
+            observe(start.elapsed());
+            Ok(response)
+        }
+        .instrument(span),
+    );
+    HostFutureIncomingResponse::pending(handle)
+}
+
+async fn send_with_limits(
+    mut request: Request<HyperOutgoingBody>,
+    mut config: OutgoingRequestConfig,
+    network: &NetworkPolicy,
+    limits: &HttpLimits,
+) -> Result<IncomingResponse, ErrorCode> {
+    let mut redirects = 0;
+    loop {
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        let headers = request.headers().clone();
+        let response = default_send_request_handler(request, clone_config(&config)).await?;
+
+        // Only requests without a body can be repeated at the new location
+        let location = response.resp.headers().get(LOCATION).and_then(|value| value.to_str().ok());
+        let follow = response.resp.status().is_redirection() && (method == Method::GET || method == Method::HEAD);
+        let Some(location) = location.filter(|_| follow) else {
+            tracing::info!(status = response.resp.status().as_u16(), redirects, "received response");
+            return limit_body(response, limits.max_body_bytes);
+        };
+
+        if redirects >= limits.max_redirects {
+            tracing::warn!(%uri, redirects, "gave up following redirects");
+            return Err(ErrorCode::InternalError(Some(format!(
+                "more than {} redirects",
+                limits.max_redirects
+            ))));
+        }
+        redirects += 1;
+
+        let current = Url::parse(&uri.to_string()).map_err(|_| ErrorCode::HttpProtocolError)?;
+        let next = current.join(location).map_err(|_| ErrorCode::HttpProtocolError)?;
+        // A redirect is a new request, so it has to pass the allowlist as well
+        if let Err(reason) = network.check_url(&next) {
+            tracing::warn!(uri = %next, %reason, "denied guest redirect");
+            return Err(ErrorCode::HttpRequestDenied);
+        }
+
+        let next_uri: Uri = next.as_str().parse().map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+        let mut builder = Request::builder().method(method).uri(next_uri);
+        // Credentials are only meant for the origin the guest sent them to
+        let cross_origin = current.origin() != next.origin();
+        let forwarded = headers
+            .iter()
+            .filter(|(name, _)| **name != HOST && !(cross_origin && CREDENTIALS.contains(*name)));
+        for (name, value) in forwarded {
+            builder = builder.header(name, value);
+        }
+        if let Some(authority) = next.host_str() {
+            let authority = match next.port() {
+                Some(port) => format!("{}:{}", authority, port),
+                None => authority.to_string(),
+            };
+            builder = builder.header(HOST, authority);
+        }
+        request = builder
+            .body(Empty::new().map_err(|never| match never {}).boxed())
+            .map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+        config.use_tls = next.scheme() == "https";
+    }
+}
+
+// Headers dropped when a redirect leads to another origin, as browsers and
+// most HTTP clients do.
+const CREDENTIALS: [HeaderName; 3] = [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+fn clone_config(config: &OutgoingRequestConfig) -> OutgoingRequestConfig {
+    OutgoingRequestConfig {
+        use_tls: config.use_tls,
+        connect_timeout: config.connect_timeout,
+        first_byte_timeout: config.first_byte_timeout,
+        between_bytes_timeout: config.between_bytes_timeout,
+    }
+}
+
+// Rejects responses that announce a body above the limit, and cuts off the
+// ones that turn out to be larger while the guest reads them.
+fn limit_body(response: IncomingResponse, max_body_bytes: u64) -> Result<IncomingResponse, ErrorCode> {
+    let IncomingResponse { resp, worker, between_bytes_timeout } = response;
+    let too_large = resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|length| length > max_body_bytes);
+    if too_large {
+        return Err(ErrorCode::HttpResponseBodySize(Some(max_body_bytes)));
+    }
+
+    let resp = resp.map(|body: HyperIncomingBody| {
+        Limited::new(body, max_body_bytes as usize)
+            .map_err(move |err| match err.downcast::<ErrorCode>() {
+                Ok(code) => *code,
+                Err(_) => ErrorCode::HttpResponseBodySize(Some(max_body_bytes)),
+            })
+            .boxed()
+    });
+    Ok(IncomingResponse { resp, worker, between_bytes_timeout })
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use url::Url;
+
+// Outbound network policy for requests made on behalf of a guest.
+// A request is allowed when at least one rule matches it, so an empty
+// policy denies everything.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct NetworkPolicy {
+    pub allow: Vec<Rule>,
+}
+
+// A single allowlist entry. Every list that is left empty matches any value.
+// Hosts may start with `*.` to match any subdomain.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Rule {
+    pub schemes: Vec<String>,
+    pub hosts: Vec<String>,
+    pub ports: Vec<u16>,
+    pub path_prefixes: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, scheme: &str, host: &str, port: u16, path: &str) -> bool {
+        (self.schemes.is_empty() || self.schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme)))
+            && (self.hosts.is_empty() || self.hosts.iter().any(|pattern| host_matches(pattern, host)))
+            && (self.ports.is_empty() || self.ports.contains(&port))
+            && (self.path_prefixes.is_empty() || self.path_prefixes.iter().any(|prefix| path.starts_with(prefix.as_str())))
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .to_ascii_lowercase()
+            .ends_with(&format!(".{}", domain.to_ascii_lowercase())),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+// Removes the dot segments of a path the way the server resolves them, also
+// when they are percent-encoded, so that `/api/../admin` is checked as `/admin`.
+// Encoded separators and backslashes could hide dot segments from this, so
+// paths containing them give None.
+fn normalize_path(path: &str) -> Option<String> {
+    let lowercase = path.to_ascii_lowercase();
+    if path.contains('\\') || lowercase.contains("%2f") || lowercase.contains("%5c") {
+        return None;
+    }
+    let mut normalized: Vec<&str> = Vec::new();
+    let mut segments = path.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        match segment.replace("%2e", ".").replace("%2E", ".").as_str() {
+            dots @ ("." | "..") => {
+                // The empty segment before the leading slash is the root
+                if dots == ".." && normalized.len() > 1 {
+                    normalized.pop();
+                }
+                if last {
+                    normalized.push("");
+                }
+            }
+            _ => normalized.push(segment),
+        }
+    }
+    Some(normalized.join("/"))
+}
+
+impl NetworkPolicy {
+    /// Checks a request against the allowlist.
+    /// Returns the reason as an error when the request is denied.
+    pub fn check(&self, scheme: &str, host: &str, port: u16, path: &str) -> Result<(), String> {
+        let Some(normalized) = normalize_path(path) else {
+            return Err(format!("{} has an encoded path separator", path));
+        };
+        if self.allow.iter().any(|rule| rule.matches(scheme, host, port, &normalized)) {
+            Ok(())
+        } else {
+            Err(format!("{}://{}:{}{} is not in the network allowlist", scheme, host, port, path))
+        }
+    }
+
+    /// Checks a parsed URL against the allowlist.
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        let host = url.host_str().ok_or_else(|| format!("{} has no host", url))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| format!("{} has no port", url))?;
+        self.check(url.scheme(), host, port, url.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(rule: Rule) -> NetworkPolicy {
+        NetworkPolicy { allow: vec![rule] }
+    }
+
+    #[test]
+    fn empty_policy_denies_everything() {
+        let policy = NetworkPolicy::default();
+        assert!(policy.check("http", "localhost", 80, "/").is_err());
+    }
+
+    #[test]
+    fn empty_lists_match_anything() {
+        let policy = policy(Rule::default());
+        assert!(policy.check("http", "localhost", 8888, "/").is_ok());
+        assert!(policy.check("https", "example.com", 443, "/any/path").is_ok());
+    }
+
+    #[test]
+    fn wildcard_hosts_match_subdomains_only() {
+        let policy = policy(Rule {
+            hosts: vec!["*.example.com".to_string()],
+            ..Rule::default()
+        });
+        assert!(policy.check("https", "api.example.com", 443, "/").is_ok());
+        assert!(policy.check("https", "a.b.EXAMPLE.com", 443, "/").is_ok());
+        assert!(policy.check("https", "example.com", 443, "/").is_err());
+        assert!(policy.check("https", "badexample.com", 443, "/").is_err());
+    }
+
+    #[test]
+    fn ports_and_schemes_are_checked() {
+        let policy = policy(Rule {
+            schemes: vec!["http".to_string()],
+            ports: vec![8888],
+            ..Rule::default()
+        });
+        assert!(policy.check("HTTP", "localhost", 8888, "/").is_ok());
+        assert!(policy.check("http", "localhost", 8889, "/").is_err());
+        assert!(policy.check("https", "localhost", 8888, "/").is_err());
+    }
+
+    #[test]
+    fn dot_segments_cannot_leave_a_path_prefix() {
+        let policy = policy(Rule {
+            path_prefixes: vec!["/api/".to_string()],
+            ..Rule::default()
+        });
+        assert!(policy.check("http", "localhost", 80, "/api/users").is_ok());
+        assert!(policy.check("http", "localhost", 80, "/api/v1/../users").is_ok());
+        assert!(policy.check("http", "localhost", 80, "/api/../admin").is_err());
+        assert!(policy.check("http", "localhost", 80, "/api/%2e%2E/admin").is_err());
+        assert!(policy.check("http", "localhost", 80, "/api/./../../api/../admin").is_err());
+        assert!(policy.check("http", "localhost", 80, "/api/..%2fadmin").is_err());
+        assert!(policy.check("http", "localhost", 80, "/api/..\\admin").is_err());
+    }
+
+    #[test]
+    fn dot_segments_are_removed() {
+        assert_eq!(normalize_path("/api/../admin").as_deref(), Some("/admin"));
+        assert_eq!(normalize_path("/../..").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/api/.").as_deref(), Some("/api/"));
+        assert_eq!(normalize_path("/a/./b/%2e%2e/c").as_deref(), Some("/a/c"));
+    }
+
+    #[test]
+    fn urls_are_checked_with_their_default_port() {
+        let policy = policy(Rule {
+            ports: vec![443],
+            path_prefixes: vec!["/api/".to_string()],
+            ..Rule::default()
+        });
+        assert!(policy.check_url(&Url::parse("https://example.com/api/x").unwrap()).is_ok());
+        assert!(policy.check_url(&Url::parse("http://example.com/api/x").unwrap()).is_err());
+        assert!(policy.check_url(&Url::parse("https://example.com/api/../admin").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use wasmtime::component::Component;
+use wasmtime::Engine;
+
+// Directory compiled components are kept in, relative to the working directory.
+// The COMPILE_CACHE environment variable overrides it.
+pub const COMPILE_CACHE_DIR: &str = ".wasm-cache";
+
+/// Loads a guest component, reusing the code compiled by an earlier run when
+/// there is one for the same wasm and engine configuration.
+pub fn load(engine: &Engine, wasm_path: impl AsRef<Path>) -> Result<Component> {
+    let wasm_path = wasm_path.as_ref();
+    let bytes = fs::read(wasm_path).with_context(|| format!("reading {}", wasm_path.display()))?;
+    let cached = cache_path(engine, &bytes);
+    if cached.exists() {
+        // The cache only holds files this host wrote with `Component::serialize`
+        // for the same engine configuration, which is what deserializing needs.
+        match unsafe { Component::deserialize_file(engine, &cached) } {
+            Ok(component) => return Ok(component),
+            Err(err) => tracing::warn!(path = %cached.display(), error = %format!("{:#}", err), "ignoring compiled component"),
+        }
+    }
+
+    let component = Component::new(engine, &bytes)?;
+    if let Err(err) = store(&cached, &component.serialize()?) {
+        tracing::warn!(path = %cached.display(), error = %format!("{:#}", err), "could not cache compiled component");
+    }
+    Ok(component)
+}
+
+/// Compiles a guest component ahead of time into the cache, so that the next
+/// start of the host does not have to. Returns the path of the compiled file.
+pub fn precompile(engine: &Engine, wasm_path: impl AsRef<Path>) -> Result<PathBuf> {
+    let wasm_path = wasm_path.as_ref();
+    let bytes = fs::read(wasm_path).with_context(|| format!("reading {}", wasm_path.display()))?;
+    let cached = cache_path(engine, &bytes);
+    store(&cached, &engine.precompile_component(&bytes)?)?;
+    Ok(cached)
+}
+
+// Compiled code is only valid for the wasm it came from and for engines with a
+// compatible configuration, so both are part of the file name.
+fn cache_path(engine: &Engine, bytes: &[u8]) -> PathBuf {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    let dir = std::env::var("COMPILE_CACHE").unwrap_or_else(|_| COMPILE_CACHE_DIR.to_string());
+    Path::new(&dir).join(format!("{:x}-{:016x}.cwasm", Sha256::digest(bytes), hasher.finish()))
+}
+
+// Feeds what a value hashes into SHA-256. Unlike the standard library's
+// hasher it gives the same result with every Rust release, so the cache keeps
+// its keys across toolchain updates.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    // The first eight bytes of the digest.
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_be_bytes(digest[..8].try_into().expect("a SHA-256 digest has 32 bytes"))
+    }
+}
+
+// Writes next to the final path first, so that a host starting at the same
+// time never reads a half written file.
+fn store(path: &Path, compiled: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension(format!("cwasm.{}", std::process::id()));
+    fs::write(&partial, compiled)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_hasher_is_stable() {
+        let mut hasher = Sha256Hasher(Sha256::new());
+        hasher.write(b"abc");
+        // The first eight bytes of SHA-256("abc")
+        assert_eq!(hasher.finish(), 0xba7816bf8f01cfea);
+    }
+}
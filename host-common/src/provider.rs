@@ -0,0 +1,310 @@
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::config::HttpLimits;
+use crate::policy::NetworkPolicy;
+use crate::stream::{Format, ModelStream};
+
+const OLLAMA_URL: &str = "http://localhost:11434";
+const OPENAI_URL: &str = "https://api.openai.com";
+const MISTRAL_URL: &str = "https://api.mistral.ai";
+
+// A service models are asked through.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProviderConfig {
+    pub kind: ProviderKind,
+    // Names of the models it serves. A name ending in `*` stands for every
+    // model starting with the rest of it.
+    pub models: Vec<String>,
+    // Base URL of the API, the provider's own service when left out.
+    #[serde(default)]
+    pub url: Option<String>,
+    // Environment variable holding the API key, sent as a bearer token.
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+}
+
+impl ProviderConfig {
+    pub fn serves(&self, model: &str) -> bool {
+        self.models.iter().any(|name| match name.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => name == model,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ProviderKind {
+    // ollama's /api/generate and /api/chat.
+    #[serde(rename = "ollama")]
+    Ollama,
+    // /v1/chat/completions as OpenAI, llama.cpp's server and vLLM serve it.
+    #[serde(rename = "openai")]
+    OpenAi,
+    // Mistral's chat completions API.
+    #[serde(rename = "mistral")]
+    Mistral,
+}
+
+// Settings answers are generated with, each left to the model when left out.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct GenerationOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub seed: Option<u64>,
+    pub max_tokens: Option<u32>,
+    // Instructions sent ahead of the conversation.
+    pub system: Option<String>,
+    // Sequences the answer ends before.
+    pub stop: Vec<String>,
+}
+
+/// Who a message of a conversation comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    // The role as chat APIs name it.
+    fn name(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+/// The service a model is served by, and how to ask it.
+pub struct Provider<'a> {
+    kind: ProviderKind,
+    url: String,
+    api_key: Option<String>,
+    client: Client,
+    // The policy requests have to pass, for hosts that give guests one.
+    network: Option<&'a NetworkPolicy>,
+    max_body_bytes: Option<u64>,
+}
+
+impl Provider<'static> {
+    /// The provider the configuration picks for a model, ollama on localhost
+    /// when none of them lists it.
+    pub fn for_model(providers: &[ProviderConfig], model: &str) -> Self {
+        let (kind, url, api_key) = match providers.iter().find(|provider| provider.serves(model)) {
+            Some(provider) => {
+                let url = provider.url.clone().unwrap_or_else(|| {
+                    match provider.kind {
+                        ProviderKind::Ollama => OLLAMA_URL,
+                        ProviderKind::OpenAi => OPENAI_URL,
+                        ProviderKind::Mistral => MISTRAL_URL,
+                    }
+                    .to_string()
+                });
+                let api_key = provider.api_key_env.as_ref().and_then(|name| std::env::var(name).ok());
+                (provider.kind, url, api_key)
+            }
+            None => (ProviderKind::Ollama, OLLAMA_URL.to_string(), None),
+        };
+        Provider {
+            kind,
+            url,
+            api_key,
+            client: Client::new(),
+            network: None,
+            max_body_bytes: None,
+        }
+    }
+}
+
+impl<'a> Provider<'a> {
+    /// Only sends requests the network policy allows, within the timeouts
+    /// and body size of the limits.
+    pub fn within(self, network: &'a NetworkPolicy, http: &HttpLimits) -> Provider<'a> {
+        let client = Client::builder()
+            .connect_timeout(http.connect_timeout())
+            .read_timeout(http.read_timeout())
+            .build()
+            .unwrap_or_default();
+        Provider {
+            client,
+            network: Some(network),
+            max_body_bytes: Some(http.max_body_bytes),
+            ..self
+        }
+    }
+
+    /// Whether the provider continues a conversation from context tokens.
+    /// Only ollama's /api/generate does, every other API takes the messages.
+    pub fn takes_context(&self) -> bool {
+        self.kind == ProviderKind::Ollama
+    }
+
+    /// Asks with a prompt and the context tokens of the answer before it.
+    /// Providers that don't take context tokens are only given the prompt.
+    #[tracing::instrument(skip(self, prompt, context, options), fields(provider = ?self.kind, context_tokens = context.len()))]
+    pub async fn generate(&self, model: &str, prompt: &str, context: &[u64], options: &GenerationOptions) -> Option<ModelStream> {
+        if !self.takes_context() {
+            let prompt = Message { role: Role::User, content: prompt.to_string() };
+            return self.chat(model, &[prompt], options).await;
+        }
+        let mut payload = json!({
+            "model": model,
+            "prompt": prompt,
+            "stream": true,
+        });
+        if !context.is_empty() {
+            payload["context"] = json!(context);
+        }
+        if let Some(system) = &options.system {
+            payload["system"] = json!(system);
+        }
+        self.add_options(&mut payload, options);
+        self.send("/api/generate", payload, Format::Ndjson).await
+    }
+
+    /// Asks with the conversation as chat messages, the last one being the
+    /// prompt, after the system message of the options if they set one.
+    #[tracing::instrument(skip(self, messages, options), fields(provider = ?self.kind, messages = messages.len()))]
+    pub async fn chat(&self, model: &str, messages: &[Message], options: &GenerationOptions) -> Option<ModelStream> {
+        let system = options.system.iter().map(|system| json!({ "role": "system", "content": system }));
+        let messages: Vec<Value> = system
+            .chain(messages.iter().map(|message| json!({ "role": message.role.name(), "content": &message.content })))
+            .collect();
+        let mut payload = json!({
+            "model": model,
+            "messages": messages,
+            "stream": true,
+        });
+        self.add_options(&mut payload, options);
+        match self.kind {
+            ProviderKind::Ollama => self.send("/api/chat", payload, Format::Ndjson).await,
+            ProviderKind::OpenAi | ProviderKind::Mistral => self.send("/v1/chat/completions", payload, Format::Sse).await,
+        }
+    }
+
+    // Adds the sampling options under the names the provider knows them by:
+    // ollama takes them in `options`, chat completions next to the messages.
+    fn add_options(&self, payload: &mut Value, options: &GenerationOptions) {
+        let mut settings = Map::new();
+        if let Some(temperature) = options.temperature {
+            settings.insert("temperature".to_string(), float(temperature));
+        }
+        if let Some(top_p) = options.top_p {
+            settings.insert("top_p".to_string(), float(top_p));
+        }
+        if let Some(seed) = options.seed {
+            let name = if self.kind == ProviderKind::Mistral { "random_seed" } else { "seed" };
+            settings.insert(name.to_string(), json!(seed));
+        }
+        if let Some(max_tokens) = options.max_tokens {
+            let name = if self.kind == ProviderKind::Ollama { "num_predict" } else { "max_tokens" };
+            settings.insert(name.to_string(), json!(max_tokens));
+        }
+        if !options.stop.is_empty() {
+            settings.insert("stop".to_string(), json!(options.stop));
+        }
+        if settings.is_empty() {
+            return;
+        }
+        match self.kind {
+            ProviderKind::Ollama => payload["options"] = Value::Object(settings),
+            ProviderKind::OpenAi | ProviderKind::Mistral => {
+                if let Some(payload) = payload.as_object_mut() {
+                    payload.extend(settings);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, path: &str, payload: Value, format: Format) -> Option<ModelStream> {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), path);
+        let Ok(url) = Url::parse(&url) else {
+            tracing::warn!(%url, "invalid model URL");
+            return None;
+        };
+        if let Some(Err(reason)) = self.network.map(|network| network.check_url(&url)) {
+            tracing::warn!(%url, %reason, "denied guest request");
+            return None;
+        }
+        let mut request = self.client.post(url.clone()).json(&payload);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::warn!(%url, error = %err, "could not reach the model");
+                return None;
+            }
+        };
+        if !response.status().is_success() {
+            tracing::warn!(%url, status = response.status().as_u16(), "the model refused the request");
+            return None;
+        }
+        let stream = ModelStream::new(response, format);
+        Some(match self.max_body_bytes {
+            Some(max_bytes) => stream.with_limit(max_bytes),
+            None => stream,
+        })
+    }
+}
+
+// Widening an f32 directly would send 0.7 as 0.699999988079071.
+fn float(value: f32) -> Value {
+    json!(value.to_string().parse::<f64>().unwrap_or(value.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(kind: &str) -> Provider<'static> {
+        let providers: Vec<ProviderConfig> =
+            serde_json::from_value(json!([{ "kind": kind, "models": ["model"] }])).unwrap();
+        Provider::for_model(&providers, "model")
+    }
+
+    fn options(provider: &Provider, options: &GenerationOptions) -> Value {
+        let mut payload = json!({});
+        provider.add_options(&mut payload, options);
+        payload
+    }
+
+    #[test]
+    fn unlisted_models_go_to_ollama() {
+        let providers: Vec<ProviderConfig> =
+            serde_json::from_value(json!([{ "kind": "openai", "models": ["gpt-*"] }])).unwrap();
+        assert!(!Provider::for_model(&providers, "gpt-4o").takes_context());
+        assert!(Provider::for_model(&providers, "mistral").takes_context());
+    }
+
+    #[test]
+    fn floats_are_sent_as_written() {
+        let set = GenerationOptions { temperature: Some(0.7), top_p: Some(0.9), ..Default::default() };
+        assert_eq!(options(&provider("ollama"), &set), json!({ "options": { "temperature": 0.7, "top_p": 0.9 } }));
+    }
+
+    #[test]
+    fn unset_options_are_left_out() {
+        assert_eq!(options(&provider("ollama"), &GenerationOptions::default()), json!({}));
+    }
+
+    #[test]
+    fn options_take_the_names_of_the_provider() {
+        let set = GenerationOptions { seed: Some(7), max_tokens: Some(100), ..Default::default() };
+        assert_eq!(options(&provider("ollama"), &set), json!({ "options": { "seed": 7, "num_predict": 100 } }));
+        assert_eq!(options(&provider("openai"), &set), json!({ "seed": 7, "max_tokens": 100 }));
+        assert_eq!(options(&provider("mistral"), &set), json!({ "random_seed": 7, "max_tokens": 100 }));
+    }
+}
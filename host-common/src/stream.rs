@@ -0,0 +1,205 @@
+use reqwest::Response;
+use serde_json::Value;
+
+/// How a provider streams its answer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON object per line, as ollama sends them.
+    Ndjson,
+    /// Server-sent events carrying OpenAI style chat completion chunks,
+    /// ending with `data: [DONE]`.
+    Sse,
+}
+
+/// A piece of an answer, the same whichever provider sent it.
+pub struct Piece {
+    pub text: String,
+    /// The context tokens ollama's /api/generate sends with its last line.
+    pub context: Option<Vec<u64>>,
+}
+
+/// An answer the model is still writing, read one line at a time as the
+/// chunks of the response arrive.
+pub struct ModelStream {
+    response: Response,
+    format: Format,
+    // Bytes received after the last complete line
+    pending: Vec<u8>,
+    // Whether the response has ended
+    done: bool,
+    // Whether the provider said the answer is complete
+    finished: bool,
+    // Bytes of the response read so far, and how many it may have
+    received: u64,
+    max_bytes: Option<u64>,
+}
+
+impl ModelStream {
+    pub fn new(response: Response, format: Format) -> Self {
+        ModelStream {
+            response,
+            format,
+            pending: Vec::new(),
+            done: false,
+            finished: false,
+            received: 0,
+            max_bytes: None,
+        }
+    }
+
+    /// Fails the answer once its response grows past `max_bytes`.
+    pub fn with_limit(self, max_bytes: u64) -> Self {
+        ModelStream { max_bytes: Some(max_bytes), ..self }
+    }
+
+    /// Returns the next piece of the answer, None once it is complete.
+    /// An answer that ends before the provider says it is complete, because
+    /// the connection broke or the model reported an error, is an error.
+    pub async fn next_piece(&mut self) -> Result<Option<Piece>, String> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+            let Some(line) = self.next_line().await? else {
+                return Err("the answer ended before the model finished it".to_string());
+            };
+            let json = match self.format {
+                Format::Ndjson => line.as_str(),
+                // Other fields of an event, and comments, carry no answer
+                Format::Sse => match line.strip_prefix("data:").map(str::trim) {
+                    Some("[DONE]") => {
+                        self.finish();
+                        return Ok(None);
+                    }
+                    Some(data) => data,
+                    None => continue,
+                },
+            };
+            let Ok(json) = serde_json::from_str::<Value>(json) else {
+                continue;
+            };
+            if let Some(error) = json.get("error") {
+                self.finish();
+                return Err(format!("the model reported an error: {}", error));
+            }
+            // Ollama's last line says it is done, and may carry text and context
+            if self.format == Format::Ndjson && json["done"].as_bool() == Some(true) {
+                self.finish();
+            }
+            return Ok(Some(piece(&json)));
+        }
+    }
+
+    // Stops reading, the answer is complete or has failed.
+    fn finish(&mut self) {
+        self.finished = true;
+        self.pending.clear();
+    }
+
+    /// Returns the next non-empty line, None once the response has ended.
+    async fn next_line(&mut self) -> Result<Option<String>, String> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if !line.is_empty() {
+                    return Ok(Some(line));
+                }
+                continue;
+            }
+            if self.done {
+                // The last line may not end with a newline
+                let rest = std::mem::take(&mut self.pending);
+                let rest = String::from_utf8_lossy(&rest).trim().to_string();
+                return Ok((!rest.is_empty()).then_some(rest));
+            }
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => {
+                    self.received += chunk.len() as u64;
+                    if let Some(max_bytes) = self.max_bytes.filter(|max_bytes| self.received > *max_bytes) {
+                        self.done = true;
+                        return Err(format!("the model response exceeds {} bytes", max_bytes));
+                    }
+                    self.pending.extend_from_slice(&chunk);
+                }
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Err(format!("the model response ended early: {}", err));
+                }
+            }
+        }
+    }
+}
+
+// Takes the text out of whichever shape the provider sends: `response` from
+// /api/generate, `message.content` from /api/chat, and `choices[0].delta.content`
+// from chat completions.
+fn piece(json: &Value) -> Piece {
+    let text = json["response"]
+        .as_str()
+        .or(json["message"]["content"].as_str())
+        .or(json["choices"][0]["delta"]["content"].as_str())
+        .unwrap_or_default();
+    let context = json["context"]
+        .as_array()
+        .map(|context| context.iter().filter_map(Value::as_u64).collect());
+    Piece {
+        text: text.to_string(),
+        context,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(body: &'static str, format: Format) -> ModelStream {
+        ModelStream::new(http::Response::new(body).into(), format)
+    }
+
+    async fn text(stream: &mut ModelStream) -> Result<String, String> {
+        let mut text = String::new();
+        while let Some(piece) = stream.next_piece().await? {
+            text.push_str(&piece.text);
+        }
+        Ok(text)
+    }
+
+    #[tokio::test]
+    async fn ollama_answers_end_with_done() {
+        let body = "{\"response\":\"Hel\",\"done\":false}\n{\"response\":\"lo\",\"done\":false}\n{\"response\":\"\",\"done\":true,\"context\":[1,2]}";
+        let mut answer = stream(body, Format::Ndjson);
+        assert_eq!(text(&mut answer).await.as_deref(), Ok("Hello"));
+    }
+
+    #[tokio::test]
+    async fn chat_completions_end_with_done_event() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n: keep-alive\n\ndata: [DONE]\n\n";
+        let mut answer = stream(body, Format::Sse);
+        assert_eq!(text(&mut answer).await.as_deref(), Ok("Hi"));
+    }
+
+    #[tokio::test]
+    async fn answers_without_an_end_are_errors() {
+        let mut answer = stream("{\"response\":\"Hel\",\"done\":false}\n", Format::Ndjson);
+        assert!(text(&mut answer).await.is_err());
+        let mut answer = stream("data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n", Format::Sse);
+        assert!(text(&mut answer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn model_errors_are_errors() {
+        let mut answer = stream("{\"response\":\"Hel\",\"done\":false}\n{\"error\":\"out of memory\"}\n", Format::Ndjson);
+        assert!(text(&mut answer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_past_the_limit_are_errors() {
+        let body = "{\"response\":\"Hello\",\"done\":true}\n";
+        let mut answer = stream(body, Format::Ndjson).with_limit(10);
+        assert!(text(&mut answer).await.is_err());
+        let mut answer = stream(body, Format::Ndjson).with_limit(body.len() as u64);
+        assert_eq!(text(&mut answer).await.as_deref(), Ok("Hello"));
+    }
+}
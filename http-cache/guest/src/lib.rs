@@ -1,5 +1,14 @@
-use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
+use crate::wasi::keyvalue::store::{self, Bucket};
+use ::wasi::clocks::wall_clock;
+use ::wasi::http::types::ErrorCode;
+use guest_common::{fetch, log};
+
+#[cfg(feature = "serve")]
+mod serve;
+mod stats;
+
+use stats::Counter;
 
 #[derive(Serialize, Deserialize)]
 struct CacheEntry {
@@ -7,97 +16,194 @@ struct CacheEntry {
     expiry: Option<u64>,
     etag: Option<String>,
     last_modified: Option<u64>,
+    // Access metadata. Entries written before it was kept read as zero.
+    // Later accesses are recorded next to the counters, see stats.rs.
+    #[serde(default)]
+    created: u64,
+    #[serde(default)]
+    last_access: u64,
+    #[serde(default)]
+    hits: u64,
+    #[serde(default)]
+    status: u16,
+    #[serde(default)]
+    content_type: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct CacheData {
-    entries: HashMap<String, CacheEntry>,
+impl CacheEntry {
+    /// Makes the entry for a response fetched at `current_time`.
+    fn from_response(response: &fetch::Response, current_time: u64) -> Self {
+        CacheEntry {
+            body: response.body.clone(),
+            // Entries without a max-age never expire.
+            expiry: response.max_age().map(|max_age| current_time + max_age),
+            etag: response.header("etag").map(String::from),
+            last_modified: None,
+            created: current_time,
+            last_access: current_time,
+            hits: 0,
+            status: response.status,
+            content_type: response.header("content-type").map(String::from),
+        }
+    }
+
+    fn is_fresh(&self, current_time: u64) -> bool {
+        self.expiry.is_none_or(|expiry| current_time <= expiry)
+    }
+}
+
+/// A response as the cache hands it out, from an entry or from the origin.
+pub struct Served {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
 }
 
-impl CacheData {
-    fn new() -> Self {
-        CacheData { entries: HashMap::new() }
+impl Served {
+    fn from_entry(entry: CacheEntry) -> Self {
+        Served {
+            // Entries cached before the status was kept were all successful
+            status: if entry.status == 0 { 200 } else { entry.status },
+            content_type: entry.content_type,
+            body: entry.body,
+        }
+    }
+
+    fn from_response(response: fetch::Response) -> Self {
+        Served {
+            status: response.status,
+            content_type: response.header("content-type").map(String::from),
+            body: response.body,
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
     }
 }
 
 pub struct FileCache {
-    file_path: String,
+    bucket: Bucket,
+    // The counters of the cache
+    stats: Bucket,
 }
 
 
 impl FileCache {
-    /// Creates a new file‑backed cache given the file path.
-    pub fn new(file_path: impl Into<String>) -> Self {
-        FileCache {
-            file_path: file_path.into(),
+    /// Opens the key-value bucket that backs the cache, and the one next to it
+    /// that keeps its counters. Returns None if the host refuses to open either.
+    pub fn open(file_path: &str) -> Option<Self> {
+        let stats_path = format!("{}{}", file_path, stats::SUFFIX);
+        let opened = store::open(file_path).and_then(|bucket| Ok(FileCache { bucket, stats: store::open(&stats_path)? }));
+        match opened {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                log::error("cache", "failed to open cache store", &[("store", &file_path), ("error", &format!("{:?}", err))]);
+                None
+            }
         }
     }
 
-    /// Loads the cache data from disk.
-    /// If the file can’t be read or parsed, returns an empty cache.
-    fn load_cache(&self) -> CacheData {
-        let contents = host::read_from_file(&self.file_path);
-        if let Ok(data) = serde_json::from_str(&contents) {
-            return data;
-        }
-        CacheData::new()
+    /// Loads a single cache entry from the store.
+    /// If the entry can’t be read or parsed, returns None.
+    fn load_entry(&self, key: &str) -> Option<CacheEntry> {
+        let bytes = self.bucket.get(key).ok()??;
+        serde_json::from_slice(&bytes).ok()
     }
 
-    /// Saves the cache data to disk.
-    fn save_cache(&self, data: &CacheData) {
-        if let Ok(json) = serde_json::to_string(data) {
-            host::write_to_file(&json, &self.file_path);
+    /// Saves a single cache entry to the store.
+    fn save_entry(&self, key: &str, entry: &CacheEntry) {
+        if let Ok(json) = serde_json::to_vec(entry) {
+            let _ = self.bucket.set(key, &json);
         }
     }
 
-    /// Adds or updates a cache entry.
-    /// For expiry and last_modified, pass None if you do not wish to set them.
-    pub fn add_response(
-        &self,
-        key: &str,
-        body: &str,
-        expiry: Option<u64>,
-        etag: Option<&str>,
-        last_modified: Option<u64>,
-    ) {
-        let mut data = self.load_cache();
-        let entry = CacheEntry {
-            body: body.to_string(),
-            expiry,
-            etag: etag.map(String::from),
-            last_modified,
-        };
-        data.entries.insert(key.to_string(), entry);
-        self.save_cache(&data);
+    /// Adds or updates the cache entry for a response fetched at `current_time`.
+    pub fn add_response(&self, key: &str, response: &fetch::Response, current_time: u64) {
+        self.save_entry(key, &CacheEntry::from_response(response, current_time));
     }
 
     /// Retrieves a cached response if it exists and is fresh.
     /// Returns None on a cache miss or if the entry is stale.
     pub fn get_response(&self, key: &str, current_time: u64) -> Option<String> {
-        let data = self.load_cache();
-        if let Some(entry) = data.entries.get(key) {
-            if let Some(expiry) = entry.expiry {
-                if current_time > expiry {
-                    return None;
-                }
-            }
-            Some(entry.body.clone())
-        } else {
-            None
-        }
+        let entry = self.load_entry(key)?;
+        entry.is_fresh(current_time).then_some(entry.body)
     }
 
-    /// Invalidates a specific cache entry.
+    /// Invalidates a specific cache entry. Only an entry that was there
+    /// counts as evicted.
     pub fn invalidate(&self, key: &str) {
-        let mut data = self.load_cache();
-        data.entries.remove(key);
-        self.save_cache(&data);
+        if !self.bucket.exists(key).unwrap_or(false) {
+            return;
+        }
+        if self.bucket.delete(key).is_ok() {
+            stats::add(&self.stats, Counter::Evictions, 1);
+            stats::forget(&self.stats, key);
+        }
     }
 
-    /// Clears all cache entries.
+    /// Clears all cache entries. The counters are kept.
     pub fn clear(&self) {
-        let data = CacheData::new();
-        self.save_cache(&data);
+        for key in self.keys() {
+            self.invalidate(&key);
+        }
+    }
+
+    /// Lists the metadata of every entry, without the bodies.
+    pub fn entries(&self) -> Vec<EntryInfo> {
+        self.keys()
+            .into_iter()
+            .filter_map(|key| {
+                let entry = self.load_entry(&key)?;
+                let (hits, last_access) = stats::access(&self.stats, &key);
+                Some(EntryInfo {
+                    size: entry.body.len() as u64,
+                    status: entry.status,
+                    content_type: entry.content_type,
+                    etag: entry.etag,
+                    created: entry.created,
+                    // Entries served before accesses were kept apart still count their own
+                    last_access: last_access.unwrap_or(entry.last_access).max(entry.last_access),
+                    hits: entry.hits + hits,
+                    expiry: entry.expiry,
+                    key,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the counters of the cache.
+    pub fn stats(&self) -> CacheStats {
+        stats::read(&self.stats)
+    }
+
+    /// Adds to one of the counters of the cache.
+    fn count(&self, counter: Counter, delta: u64) {
+        stats::add(&self.stats, counter, delta);
+    }
+
+    /// Answers a lookup from an entry and records the access, with the counter
+    /// saying how the entry was found. The entry itself is left as it is, so
+    /// a newer response stored by another call at the same time is kept.
+    fn serve(&self, key: &str, entry: CacheEntry, counter: Counter, current_time: u64) -> Served {
+        stats::record_access(&self.stats, key, current_time);
+        self.count(counter, 1);
+        self.count(Counter::BytesServed, entry.body.len() as u64);
+        Served::from_entry(entry)
+    }
+
+    /// Lists every entry key in the store, following the pagination cursor.
+    fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        while let Ok(page) = self.bucket.list_keys(cursor) {
+            keys.extend(page.keys);
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        keys
     }
 
 }
@@ -107,33 +213,148 @@ impl FileCache {
 wit_bindgen::generate!({
     path: "wit",
     world: "myworld",
+    generate_all,
 });
 
 
-struct MyHost;
+/// Returns the cached response for `key`, fetching and caching it on a miss.
+/// A stale entry with an ETag is revalidated with the origin, and a stale entry
+/// is served as is when the origin can't be reached. A resource that is gone
+/// from the origin gives the origin's response, which is not cached.
+fn lookup(cache: &FileCache, key: &str, current_time: u64) -> Option<Served> {
+    let entry = match cache.load_entry(key) {
+        Some(entry) if entry.is_fresh(current_time) => {
+            log::info("cache", "cache hit", &[("key", &key)]);
+            return Some(cache.serve(key, entry, Counter::Hits, current_time));
+        }
+        entry => entry,
+    };
 
-impl Guest for MyHost {
-    fn get_or_fetch(file_path: String, key: String, current_time: u64) -> Option<String> {
-        let cache = FileCache::new(file_path);
-        if let Some(cached) = cache.get_response(&key, current_time) {
-            println!("Cache hit for {}", key);
-            Some(cached)
-        } else {
-            println!("Cache miss or stale entry for {}. Fetching from network...", key);
-            match host::manual_get(&key) {
-                Some(body) => {
-                    // For demonstration, we set no expiry.
-                    // In practice, you might set an expiry based on headers.
-                    cache.add_response(&key, &body, None, None, None);
-                    Some(body)
-                },
-                None => {
-                    eprintln!("Failed to fetch response from network for {}", key);
-                    None
+    log::info("cache", "cache miss or stale entry, fetching from network", &[("key", &key)]);
+    let etag = entry.as_ref().and_then(|entry| entry.etag.clone());
+    let headers: Vec<(&str, &str)> = etag.iter().map(|etag| ("if-none-match", etag.as_str())).collect();
+    match (fetch::get(key, &headers), entry) {
+        (Ok(response), Some(mut entry)) if response.status == 304 => {
+            log::info("cache", "stale entry revalidated", &[("key", &key)]);
+            // Without a new max-age the entry stays stale and is revalidated again next time
+            if let Some(max_age) = response.max_age() {
+                entry.expiry = Some(current_time + max_age);
+                cache.save_entry(key, &entry);
+            }
+            Some(cache.serve(key, entry, Counter::Revalidations, current_time))
+        },
+        (Ok(response), entry) if (200..300).contains(&response.status) => {
+            log::info("fetch", "fetched response", &[("key", &key), ("bytes", &response.body.len())]);
+            cache.count(Counter::Misses, 1);
+            cache.count(Counter::BytesFetched, response.body.len() as u64);
+            cache.count(Counter::BytesServed, response.body.len() as u64);
+            if response.no_store() {
+                if entry.is_some() {
+                    cache.invalidate(key);
                 }
+                return Some(Served::from_response(response));
             }
+            cache.add_response(key, &response, current_time);
+            Some(Served::from_response(response))
+        },
+        (Ok(response), entry) if matches!(response.status, 404 | 410) => {
+            log::warn("fetch", "resource is gone from the origin", &[("key", &key), ("status", &response.status)]);
+            if entry.is_some() {
+                cache.invalidate(key);
+            }
+            cache.count(Counter::Misses, 1);
+            Some(Served::from_response(response))
+        },
+        (Ok(response), entry) => {
+            log::warn("fetch", "request failed", &[("key", &key), ("status", &response.status)]);
+            serve_stale(cache, key, entry, current_time)
+        },
+        (Err(ErrorCode::HttpRequestDenied), entry) => {
+            log::warn("fetch", "request denied by the host network policy", &[("key", &key)]);
+            serve_stale(cache, key, entry, current_time)
+        },
+        (Err(err), entry) => {
+            log::error("fetch", "failed to fetch response from network", &[("key", &key), ("error", &format!("{:?}", err))]);
+            serve_stale(cache, key, entry, current_time)
+        }
+    }
+}
+
+/// Falls back to a stale entry when the origin gave no usable response.
+fn serve_stale(cache: &FileCache, key: &str, entry: Option<CacheEntry>, current_time: u64) -> Option<Served> {
+    match entry {
+        Some(entry) => {
+            log::warn("cache", "serving stale entry", &[("key", &key)]);
+            Some(cache.serve(key, entry, Counter::StaleServes, current_time))
+        }
+        None => {
+            cache.count(Counter::Misses, 1);
+            None
         }
     }
 }
 
+struct MyHost;
+
+impl Guest for MyHost {
+    fn get_or_fetch(file_path: String, key: String) -> Option<String> {
+        let cache = FileCache::open(&file_path)?;
+        lookup(&cache, &key, wall_clock::now().seconds)
+            .filter(Served::is_success)
+            .map(|served| served.body)
+    }
+
+    fn list_entries(file_path: String) -> Option<Vec<EntryInfo>> {
+        Some(FileCache::open(&file_path)?.entries())
+    }
+
+    fn stats(file_path: String) -> Option<CacheStats> {
+        Some(FileCache::open(&file_path)?.stats())
+    }
+}
+
 export!(MyHost);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fixed timestamp the entries below are fetched at, so that their
+    // expiry does not depend on when the tests run.
+    const START: u64 = 1_700_000_000;
+
+    fn response(cache_control: Option<&str>) -> fetch::Response {
+        let headers: Vec<(&str, &str)> = cache_control.iter().map(|value| ("cache-control", *value)).collect();
+        fetch::Response::new(200, &headers, "body")
+    }
+
+    #[test]
+    fn entry_is_fresh_until_its_max_age_has_passed() {
+        let entry = CacheEntry::from_response(&response(Some("public, max-age=60")), START);
+        assert_eq!(entry.expiry, Some(START + 60));
+        assert!(entry.is_fresh(START));
+        assert!(entry.is_fresh(START + 60));
+        assert!(!entry.is_fresh(START + 61));
+    }
+
+    #[test]
+    fn expiry_counts_from_the_time_the_response_was_fetched() {
+        let later = START + 3600;
+        let entry = CacheEntry::from_response(&response(Some("max-age=60")), later);
+        assert!(entry.is_fresh(later + 60));
+        assert!(!entry.is_fresh(later + 61));
+    }
+
+    #[test]
+    fn entry_without_max_age_never_expires() {
+        let entry = CacheEntry::from_response(&response(None), START);
+        assert_eq!(entry.expiry, None);
+        assert!(entry.is_fresh(u64::MAX));
+    }
+
+    #[test]
+    fn invalid_max_age_is_ignored() {
+        let entry = CacheEntry::from_response(&response(Some("max-age=soon")), START);
+        assert_eq!(entry.expiry, None);
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use ::wasi::clocks::wall_clock;
+use ::wasi::exports::http::incoming_handler::Guest as IncomingHandler;
+use ::wasi::http::types::{
+    Fields, IncomingRequest, Method, OutgoingBody, OutgoingResponse, ResponseOutparam,
+};
+
+use crate::FileCache;
+
+// Used when the environment does not say where to fetch from or cache to.
+const DEFAULT_ORIGIN: &str = "http://localhost:8888";
+const DEFAULT_STORE: &str = "./data.json";
+
+/// Serves the cache as a wasi:http component.
+///
+/// The path of every GET request is looked up under `CACHE_ORIGIN`, and the
+/// responses are kept in the `CACHE_STORE` bucket, both read from the
+/// environment the host gives the component.
+struct CacheServer;
+
+impl IncomingHandler for CacheServer {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        if !matches!(request.method(), Method::Get) {
+            respond(response_out, 405, None, b"Only GET requests are cached\n");
+            return;
+        }
+
+        let origin = std::env::var("CACHE_ORIGIN").unwrap_or_else(|_| DEFAULT_ORIGIN.to_string());
+        let store = std::env::var("CACHE_STORE").unwrap_or_else(|_| DEFAULT_STORE.to_string());
+        let path = request.path_with_query().unwrap_or_else(|| "/".to_string());
+        let key = format!("{}{}", origin.trim_end_matches('/'), path);
+
+        let Some(cache) = FileCache::open(&store) else {
+            respond(response_out, 500, None, b"Cache store is unavailable\n");
+            return;
+        };
+        // Responses keep the status and type the origin gave them
+        match crate::lookup(&cache, &key, wall_clock::now().seconds) {
+            Some(served) => respond(response_out, served.status, served.content_type.as_deref(), served.body.as_bytes()),
+            None => respond(response_out, 502, None, b"Failed to fetch response from origin\n"),
+        }
+    }
+}
+
+// Sends a complete response with the given status, content type and body.
+fn respond(response_out: ResponseOutparam, status: u16, content_type: Option<&str>, body: &[u8]) {
+    let headers = Fields::new();
+    let _ = headers.set(&"content-length".to_string(), &[body.len().to_string().into_bytes()]);
+    if let Some(content_type) = content_type {
+        let _ = headers.set(&"content-type".to_string(), &[content_type.as_bytes().to_vec()]);
+    }
+    let response = OutgoingResponse::new(headers);
+    let _ = response.set_status_code(status);
+    let outgoing = response.body().expect("response body is only taken once");
+    ResponseOutparam::set(response_out, Ok(response));
+
+    {
+        let mut stream = outgoing.write().expect("body stream is only taken once");
+        if let Err(err) = stream.write_all(body).and_then(|_| Write::flush(&mut stream)) {
+            guest_common::log::error("serve", "failed to write response body", &[("error", &err)]);
+        }
+    }
+    let _ = OutgoingBody::finish(outgoing, None);
+}
+
+::wasi::http::proxy::export!(CacheServer);
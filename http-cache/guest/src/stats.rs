@@ -0,0 +1,94 @@
+use crate::wasi::keyvalue::atomics;
+use crate::wasi::keyvalue::store::Bucket;
+use crate::CacheStats;
+use guest_common::log;
+
+// Counters live in a bucket of their own next to the cache's, so they can
+// never clash with the URL of an entry.
+pub const SUFFIX: &str = ".stats";
+
+#[derive(Clone, Copy)]
+pub enum Counter {
+    Hits,
+    Misses,
+    StaleServes,
+    Revalidations,
+    Evictions,
+    BytesServed,
+    BytesFetched,
+}
+
+impl Counter {
+    fn key(self) -> &'static str {
+        match self {
+            Counter::Hits => "hits",
+            Counter::Misses => "misses",
+            Counter::StaleServes => "stale-serves",
+            Counter::Revalidations => "revalidations",
+            Counter::Evictions => "evictions",
+            Counter::BytesServed => "bytes-served",
+            Counter::BytesFetched => "bytes-fetched",
+        }
+    }
+}
+
+/// Adds to a counter. A counter that can't be updated is logged and skipped,
+/// the lookup itself still succeeds.
+pub fn add(bucket: &Bucket, counter: Counter, delta: u64) {
+    if let Err(err) = atomics::increment(bucket, counter.key(), delta) {
+        log::warn("stats", "failed to update counter", &[("counter", &counter.key()), ("error", &format!("{:?}", err))]);
+    }
+}
+
+// Every entry counts its hits and records its last access under keys of its
+// own here, so that serving an entry never has to write the entry back.
+fn hits_key(entry: &str) -> String {
+    format!("hits/{}", entry)
+}
+
+fn last_access_key(entry: &str) -> String {
+    format!("last-access/{}", entry)
+}
+
+/// Records that an entry was served at `current_time`.
+pub fn record_access(bucket: &Bucket, entry: &str, current_time: u64) {
+    if let Err(err) = atomics::increment(bucket, &hits_key(entry), 1) {
+        log::warn("stats", "failed to count entry hit", &[("key", &entry), ("error", &format!("{:?}", err))]);
+    }
+    let _ = bucket.set(&last_access_key(entry), current_time.to_string().as_bytes());
+}
+
+/// The hits of an entry and when it was last served, if it was.
+pub fn access(bucket: &Bucket, entry: &str) -> (u64, Option<u64>) {
+    (number(bucket, &hits_key(entry)).unwrap_or(0), number(bucket, &last_access_key(entry)))
+}
+
+/// Forgets the access record of an entry that was removed.
+pub fn forget(bucket: &Bucket, entry: &str) {
+    let _ = bucket.delete(&hits_key(entry));
+    let _ = bucket.delete(&last_access_key(entry));
+}
+
+// Reads a value kept as decimal text.
+fn number(bucket: &Bucket, key: &str) -> Option<u64> {
+    bucket
+        .get(key)
+        .ok()
+        .flatten()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|text| text.parse().ok())
+}
+
+/// Reads every counter, missing ones count as zero.
+pub fn read(bucket: &Bucket) -> CacheStats {
+    let get = |counter: Counter| number(bucket, counter.key()).unwrap_or(0);
+    CacheStats {
+        hits: get(Counter::Hits),
+        misses: get(Counter::Misses),
+        stale_serves: get(Counter::StaleServes),
+        revalidations: get(Counter::Revalidations),
+        evictions: get(Counter::Evictions),
+        bytes_served: get(Counter::BytesServed),
+        bytes_fetched: get(Counter::BytesFetched),
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use wasmtime_wasi::WasiCtxBuilder;
+
+use crate::config::{HostConfig, PoolConfig};
+use crate::{build_engine, instance_pre, new_store, MyState, MyworldPre};
+
+pub const DEFAULT_CALLS: usize = 1000;
+
+// Every call asks for the same entry. The warm-up call caches it, so the timed
+// calls measure the host and the guest rather than the origin.
+const BENCH_STORE: &str = "./bench.json";
+const BENCH_KEY: &str = "http://localhost:8888";
+
+/// Makes the given number of calls, each on a new instance of the guest, once
+/// with instances allocated on demand and once from the pooling allocator.
+pub async fn run(calls: usize, config: Arc<HostConfig>) -> Result<()> {
+    let pool = config.pool.clone().unwrap_or_default();
+    let on_demand = measure(&config, None, calls).await?;
+    report("on-demand", calls, on_demand);
+    let pooled = measure(&config, Some(&pool), calls).await?;
+    report("pooling", calls, pooled);
+    println!(
+        "pooling is {:.2}x the speed of on-demand allocation",
+        on_demand.as_secs_f64() / pooled.as_secs_f64()
+    );
+    Ok(())
+}
+
+async fn measure(config: &Arc<HostConfig>, pool: Option<&PoolConfig>, calls: usize) -> Result<Duration> {
+    let engine = build_engine(config, pool)?;
+    let pre = MyworldPre::new(instance_pre(&engine)?)?;
+    call(&pre, config).await?;
+
+    let start = Instant::now();
+    for _ in 0..calls {
+        call(&pre, config).await?;
+    }
+    Ok(start.elapsed())
+}
+
+// Instantiates the guest and makes a single call, the way serve mode handles
+// a request. The guest's output is dropped so it does not end up in the timings.
+async fn call(pre: &MyworldPre<MyState>, config: &Arc<HostConfig>) -> Result<()> {
+    let mut store = new_store(pre.engine(), WasiCtxBuilder::new().build(), config.clone())?;
+    let guest = pre.instantiate_async(&mut store).await?;
+    guest.call_get_or_fetch(&mut store, BENCH_STORE, BENCH_KEY).await?;
+    Ok(())
+}
+
+fn report(name: &str, calls: usize, elapsed: Duration) {
+    println!(
+        "{:>9}: {} calls in {:.2?}, {:.1} µs per call, {:.0} calls/s",
+        name,
+        calls,
+        elapsed,
+        elapsed.as_secs_f64() * 1e6 / calls.max(1) as f64,
+        calls as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,50 @@
+use host_common::config::{GuestLimits, HttpLimits};
+use host_common::outgoing::OutgoingConfig;
+use host_common::policy::NetworkPolicy;
+use serde::Deserialize;
+
+// Settings the host applies to every guest it runs.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HostConfig {
+    pub network: NetworkPolicy,
+    pub http: HttpLimits,
+    pub limits: GuestLimits,
+    // Instances come from the pooling allocator when this section is present.
+    pub pool: Option<PoolConfig>,
+}
+
+// Sizes of the pooling instance allocator. Every slot is reserved up front,
+// so `instances` caps how many guests can be alive at once.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub instances: u32,
+    pub core_instances: u32,
+    pub memories: u32,
+    pub tables: u32,
+    // Slots kept warm after their instance is gone, so reusing them is cheap.
+    pub warm_slots: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            instances: 100,
+            core_instances: 1000,
+            memories: 1000,
+            tables: 1000,
+            warm_slots: 100,
+        }
+    }
+}
+
+impl OutgoingConfig for HostConfig {
+    fn network(&self) -> &NetworkPolicy {
+        &self.network
+    }
+
+    fn http(&self) -> &HttpLimits {
+        &self.http
+    }
+}
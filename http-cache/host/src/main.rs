@@ -1,103 +1,259 @@
-use std::{fs, error::Error};
-use wasmtime::{component::{ResourceTable, bindgen, Component, Linker}, *};
+use std::{error::Error, sync::Arc};
+use host_common::{clock, keyvalue, limits, logging, outgoing, precompile};
+use wasmtime::{component::{ResourceTable, bindgen, InstancePre, Linker}, *};
 use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use reqwest::blocking::get;
-bindgen!("myworld" in "../guest-cache/wit/witfile.wit");
+mod bench;
+mod config;
+mod metrics;
+mod pool;
+mod serve;
 
-struct HostComponent;
+use config::{HostConfig, PoolConfig};
 
-// Implementation of the host interface defined in the wit file.
-impl host::Host for HostComponent {
-    fn manual_get(&mut self, url: String) -> Option<String> {
-        // Send the GET request and return None if there's an error
-        let response = get(&url).ok()?;
+bindgen!({
+    world: "myworld",
+    path: "../guest/wit",
+    async: true,
+    tracing: true,
+    with: {
+        "wasi:keyvalue/store": host_common::wasi::keyvalue::store,
+        "wasi:keyvalue/atomics": host_common::wasi::keyvalue::atomics,
+        "alireza:logging/logging": host_common::alireza::logging::logging,
+    },
+});
 
-        // Check that the request was successful
-        if !response.status().is_success() {
-            return None;
-        }
+struct MyState {
+    ctx: WasiCtx,
+    table: ResourceTable,
+    http: WasiHttpCtx,
+    keyvalue: keyvalue::KeyValue,
+    logger: logging::GuestLogger,
+    limiter: limits::GuestLimiter,
+    config: Arc<HostConfig>,
+}
+impl IoView for MyState {
+    fn table(&mut self) -> &mut ResourceTable { &mut self.table }
+}
+impl WasiView for MyState {
+    fn ctx(&mut self) -> &mut WasiCtx { &mut self.ctx }
+}
+impl WasiHttpView for MyState {
+    fn ctx(&mut self) -> &mut WasiHttpCtx { &mut self.http }
 
-        // Read the response body as bytes, returning None on error
-        let text = response.text().ok()?;
+    // Every outgoing request of the guest is checked against the network policy
+    // first, and then sent within the configured limits.
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        outgoing::send_request(request, config, self.config.clone(), |elapsed| metrics::FETCH_DURATION.observe(elapsed))
+    }
+}
 
-        println!("Fetched {} bytes", text.len());
-        Some(text)
+impl MyState {
+    fn new(ctx: WasiCtx, config: Arc<HostConfig>) -> Self {
+        MyState {
+            ctx,
+            table: ResourceTable::new(),
+            http: WasiHttpCtx::new(),
+            keyvalue: keyvalue::KeyValue::new("."),
+            logger: logging::GuestLogger,
+            limiter: limits::GuestLimiter::new(&config.limits),
+            config,
+        }
     }
+}
+
+// An instance of the cache guest. A call that traps, for example on running
+// out of fuel, leaves the instance unusable, so it is replaced with a fresh one
+// and the host can carry on with the next call.
+struct CacheGuest {
+    pre: MyworldPre<MyState>,
+    config: Arc<HostConfig>,
+    store: Store<MyState>,
+    functions: Myworld,
+}
 
-    fn write_to_file(&mut self, data: String, file_name: String) {
-        if let Ok(mut file) = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&file_name)
-    {
-        let _ = file.write_all(data.as_bytes());
+impl CacheGuest {
+    async fn new(pre: MyworldPre<MyState>, config: Arc<HostConfig>) -> anyhow::Result<Self> {
+        let (store, functions) = Self::instantiate(&pre, &config).await?;
+        Ok(CacheGuest { pre, config, store, functions })
     }
+
+    async fn instantiate(pre: &MyworldPre<MyState>, config: &Arc<HostConfig>) -> anyhow::Result<(Store<MyState>, Myworld)> {
+        let wasi_ctx = clock::install(WasiCtxBuilder::new().inherit_stdio()).build();
+        let mut store = new_store(pre.engine(), wasi_ctx, config.clone())?;
+        let functions = pre.instantiate_async(&mut store).await?;
+        Ok((store, functions))
     }
 
-    fn read_from_file(&mut self, file_name: String) -> String {
-        match File::open(&file_name) {
-            Ok(mut file) => {
-                let mut contents = String::new();
-                if file.read_to_string(&mut contents).is_ok() {
-                    return contents;
-                } else {
-                    String::from("")
-                }
-            },
-            Err(_) => String::from(""),
+    #[tracing::instrument(skip(self))]
+    async fn get_or_fetch(&mut self, file_path: &str, key: &str) -> anyhow::Result<Option<String>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = self.functions.call_get_or_fetch(&mut self.store, file_path, key).await;
+        if result.is_err() {
+            (self.store, self.functions) = Self::instantiate(&self.pre, &self.config).await?;
         }
+        result
     }
-}
 
-struct MyState {
-    ctx: WasiCtx,
-    table: ResourceTable,
-    host: HostComponent,
-}
-impl IoView for MyState {
-    fn table(&mut self) -> &mut ResourceTable { &mut self.table }
-}
-impl WasiView for MyState {
-    fn ctx(&mut self) -> &mut WasiCtx { &mut self.ctx }
+    async fn list_entries(&mut self, file_path: &str) -> anyhow::Result<Option<Vec<EntryInfo>>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        self.functions.call_list_entries(&mut self.store, file_path).await
+    }
+
+    async fn stats(&mut self, file_path: &str) -> anyhow::Result<Option<CacheStats>> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        self.functions.call_stats(&mut self.store, file_path).await
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let engine = Engine::new(Config::new().wasm_component_model(true))?;
-    let bytes = fs::read("../guest-cache/target/wasm32-wasip2/release/guest_cache.wasm")?;
-    let component = Component::new(&engine, &bytes)?;
-    let wasi_ctx = WasiCtxBuilder::new().inherit_stdio().build();
+// Bucket the demo and serve mode keep the cache in.
+const CACHE_FILE: &str = "./data.json";
 
+// Path of the compiled guest component, relative to the host crate.
+const GUEST_PATH: &str = "../guest/target/wasm32-wasip2/release/guest_cache.wasm";
 
+// Builds the engine guests run on, using the pooling allocator when a pool is given.
+// Compiled components are only reusable by engines built the same way.
+fn build_engine(config: &HostConfig, pool: Option<&PoolConfig>) -> anyhow::Result<Engine> {
+    let mut engine_config = Config::new();
+    engine_config.wasm_component_model(true).async_support(true);
+    limits::configure(&mut engine_config, &config.limits);
+    if let Some(pool) = pool {
+        pool::configure(&mut engine_config, pool, &config.limits);
+    }
+    let engine = Engine::new(&engine_config)?;
+    if config.limits.timeout_ms.is_some() {
+        limits::start_epoch_ticker(&engine);
+    }
+    Ok(engine)
+}
 
-    let mut store = wasmtime::Store::new(&engine,
-         MyState {
-            ctx: wasi_ctx,
-            table: ResourceTable::new(),
-            host: HostComponent {},
+// Compiles the guest and resolves its imports once, so that every instance
+// after that only has to be allocated and initialized.
+fn instance_pre(engine: &Engine) -> anyhow::Result<InstancePre<MyState>> {
+    let component = precompile::load(engine, GUEST_PATH)?;
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi::add_to_linker_async(&mut linker)?;
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+    host_common::wasi::keyvalue::store::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::wasi::keyvalue::atomics::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::alireza::logging::logging::add_to_linker(&mut linker, |state: &mut MyState| &mut state.logger)?;
+    linker.instantiate_pre(&component)
+}
 
-         });
-    let mut linker = Linker::new(&engine);
-    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
-    host::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
+// Creates the store of a single guest instance, with its limits in place.
+fn new_store(engine: &Engine, ctx: WasiCtx, config: Arc<HostConfig>) -> anyhow::Result<Store<MyState>> {
+    let mut store = Store::new(engine, MyState::new(ctx, config.clone()));
+    store.limiter(|state| &mut state.limiter);
+    limits::arm(&mut store, &config.limits)?;
+    Ok(store)
+}
 
-    let functions = Myworld::instantiate(&mut store, &component, &linker)?;
-    let result1 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888", 0);
-    let result2 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/leisure_data.csv", 1000);
-    let result3 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/config.json", 1000);
-    let result4 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/chart.plugin.js", 1000);
-    let result5 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/q1.jpg", 1000);
+fn main() -> Result<(), Box<dyn Error>> {
+    // `host serve [addr] [origin]` serves the cache over HTTP instead of running the demo,
+    // `host bench [calls]` compares instantiating per call with and without pooling,
+    // `host stats [store]` prints the counters of a cache store,
+    // `host entries [store]` lists what a cache store holds,
+    // and `host precompile [wasm]` compiles the guest into the compile cache.
+    let args: Vec<String> = std::env::args().collect();
+    logging::init();
+    clock::init()?;
+    let config = Arc::new(host_common::config::load::<HostConfig>()?);
+    let runtime = tokio::runtime::Runtime::new()?;
+    match args.get(1).map(String::as_str) {
+        Some("serve") => {
+            let addr = args.get(2).map(String::as_str).unwrap_or(serve::DEFAULT_ADDR);
+            let origin = args.get(3).map(String::as_str).unwrap_or(serve::DEFAULT_ORIGIN);
+            runtime.block_on(serve::run(addr, origin, config))?;
+        }
+        Some("bench") => {
+            let calls = match args.get(2) {
+                Some(calls) => calls.parse()?,
+                None => bench::DEFAULT_CALLS,
+            };
+            runtime.block_on(bench::run(calls, config))?;
+        }
+        Some("stats") => {
+            let file_path = args.get(2).map(String::as_str).unwrap_or(CACHE_FILE);
+            runtime.block_on(stats(config, file_path))?;
+        }
+        Some("entries") => {
+            let file_path = args.get(2).map(String::as_str).unwrap_or(CACHE_FILE);
+            runtime.block_on(entries(config, file_path))?;
+        }
+        Some("precompile") => {
+            let wasm_path = args.get(2).map(String::as_str).unwrap_or(GUEST_PATH);
+            let compiled = precompile::precompile(&build_engine(&config, config.pool.as_ref())?, wasm_path)?;
+            println!("Compiled {} to {}", wasm_path, compiled.display());
+        }
+        _ => runtime.block_on(run(config))?,
+    }
+    Ok(())
+}
 
+// Runs the demo requests through one instance of the guest. With MOCK_CLOCK
+// set, the clock is moved forward by the given seconds before each request.
+async fn run(config: Arc<HostConfig>) -> Result<(), Box<dyn Error>> {
+    let engine = build_engine(&config, config.pool.as_ref())?;
+    let pre = MyworldPre::new(instance_pre(&engine)?)?;
+    let mut guest = CacheGuest::new(pre, config).await?;
+    let requests = [
+        ("http://localhost:8888", 0),
+        ("http://localhost:8888/leisure_data.csv", 1000),
+        ("http://localhost:8888/config.json", 0),
+        ("http://localhost:8888/chart.plugin.js", 0),
+        // ("http://localhost:8888/q1.jpg", 0),
+    ];
+    for (url, elapsed) in requests {
+        clock::advance(elapsed);
+        match guest.get_or_fetch(CACHE_FILE, url).await {
+            Ok(Some(body)) => println!("{:?}", body),
+            Ok(None) => println!("No response for {}", url),
+            Err(err) => println!("Call for {} failed: {}", url, limits::describe(&err)),
+        }
+    }
 
-    println!("{:?}", result1.unwrap().unwrap());
-    println!("{:?}", result2.unwrap().unwrap());
-    println!("{:?}", result3.unwrap().unwrap());
-    println!("{:?}", result4.unwrap().unwrap());
-    // println!("{:?}", result5.unwrap().unwrap());
+    Ok(())
+}
 
+// Prints the counters of a cache store in the Prometheus text format.
+async fn stats(config: Arc<HostConfig>, file_path: &str) -> Result<(), Box<dyn Error>> {
+    let engine = build_engine(&config, config.pool.as_ref())?;
+    let pre = MyworldPre::new(instance_pre(&engine)?)?;
+    let mut guest = CacheGuest::new(pre, config).await?;
+    let stats = guest.stats(file_path).await?;
+    print!("{}", metrics::render(stats.as_ref()));
     Ok(())
 }
 
+// Prints the metadata of every entry in a cache store, one entry per line.
+async fn entries(config: Arc<HostConfig>, file_path: &str) -> Result<(), Box<dyn Error>> {
+    let engine = build_engine(&config, config.pool.as_ref())?;
+    let pre = MyworldPre::new(instance_pre(&engine)?)?;
+    let mut guest = CacheGuest::new(pre, config).await?;
+    let Some(entries) = guest.list_entries(file_path).await? else {
+        return Err(format!("could not open the cache store {}", file_path).into());
+    };
+    for entry in entries {
+        println!(
+            "{} status={} type={} size={} hits={} created={} last-access={} expiry={} etag={}",
+            entry.key,
+            entry.status,
+            entry.content_type.as_deref().unwrap_or("-"),
+            entry.size,
+            entry.hits,
+            entry.created,
+            entry.last_access,
+            entry.expiry.map_or("never".to_string(), |expiry| expiry.to_string()),
+            entry.etag.as_deref().unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
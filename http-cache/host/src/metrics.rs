@@ -0,0 +1,83 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::CacheStats;
+
+// Upper bounds of the fetch latency buckets, in seconds.
+const BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Time from sending a guest request to receiving its response headers,
+/// including any redirects the host followed.
+pub static FETCH_DURATION: Histogram = Histogram::new();
+
+// A Prometheus histogram that can be updated from any thread.
+pub struct Histogram {
+    // Observations per bucket, the last one counting those above every bound
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            counts: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|&bound| seconds <= bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut count = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.counts) {
+            count += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        count += self.counts[BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Renders the guest's cache counters and the host's fetch latencies in the
+/// Prometheus text format. The counters are left out when the guest could not
+/// open its store.
+pub fn render(stats: Option<&CacheStats>) -> String {
+    let mut out = String::new();
+    if let Some(stats) = stats {
+        let counters = [
+            ("cache_hits_total", "Lookups answered from a fresh entry.", stats.hits),
+            ("cache_misses_total", "Lookups that fetched the body from the origin.", stats.misses),
+            ("cache_stale_serves_total", "Lookups answered from a stale entry when the origin failed.", stats.stale_serves),
+            ("cache_revalidations_total", "Stale entries the origin confirmed unchanged.", stats.revalidations),
+            ("cache_evictions_total", "Entries removed from the cache.", stats.evictions),
+            ("cache_served_bytes_total", "Bytes of response bodies returned by the cache.", stats.bytes_served),
+            ("cache_fetched_bytes_total", "Bytes of response bodies fetched from the origin.", stats.bytes_fetched),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        // Everything but a miss is answered without fetching the body again
+        let lookups = stats.hits + stats.misses + stats.stale_serves + stats.revalidations;
+        let answered = stats.hits + stats.stale_serves + stats.revalidations;
+        let ratio = if lookups == 0 { 0.0 } else { answered as f64 / lookups as f64 };
+        let _ = writeln!(out, "# HELP cache_hit_ratio Share of lookups answered without fetching the body.");
+        let _ = writeln!(out, "# TYPE cache_hit_ratio gauge");
+        let _ = writeln!(out, "cache_hit_ratio {}", ratio);
+    }
+    FETCH_DURATION.render(&mut out, "fetch_duration_seconds", "Latency of requests the host sent for the guest.");
+    out
+}
@@ -0,0 +1,26 @@
+use host_common::config::GuestLimits;
+use wasmtime::{Config, InstanceAllocationStrategy, PoolingAllocationConfig};
+
+use crate::config::PoolConfig;
+
+/// Switches the engine to the pooling allocator, which hands out instances
+/// from slots reserved when the engine is created instead of allocating
+/// memories and tables for every instantiation.
+pub fn configure(config: &mut Config, pool: &PoolConfig, limits: &GuestLimits) {
+    let mut pooling = PoolingAllocationConfig::new();
+    pooling
+        .total_component_instances(pool.instances)
+        .total_stacks(pool.instances)
+        .total_core_instances(pool.core_instances)
+        .total_memories(pool.memories)
+        .total_tables(pool.tables)
+        .max_unused_warm_slots(pool.warm_slots);
+    // Slots only need to be as large as the guest is allowed to grow
+    if let Some(max_memory_bytes) = limits.max_memory_bytes {
+        pooling.max_memory_size(max_memory_bytes);
+    }
+    if let Some(max_table_elements) = limits.max_table_elements {
+        pooling.table_elements(max_table_elements);
+    }
+    config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+}
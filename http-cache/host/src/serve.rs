@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use host_common::clock;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use tokio::net::TcpListener;
+use tracing::Instrument;
+use wasmtime_wasi::WasiCtxBuilder;
+use wasmtime_wasi_http::bindings::http::types::Scheme;
+use wasmtime_wasi_http::bindings::ProxyPre;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::io::TokioIo;
+use wasmtime_wasi_http::WasiHttpView;
+
+use crate::config::HostConfig;
+use crate::{build_engine, instance_pre, metrics, new_store, MyState, MyworldPre, CACHE_FILE};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+pub const DEFAULT_ORIGIN: &str = "http://localhost:8888";
+
+// Serves the guest's wasi:http/incoming-handler export. The guest has to be
+// built with the `serve` feature for the export to exist. Every request gets
+// a fresh instance in its own store, like `wasmtime serve` does. `/metrics`
+// is answered by the host with the counters of the cache.
+pub async fn run(addr: &str, origin: &str, config: Arc<HostConfig>) -> Result<()> {
+    let engine = build_engine(&config, config.pool.as_ref())?;
+    let linked = instance_pre(&engine)?;
+    let pre = ProxyPre::new(linked.clone())?;
+    let cache_pre = MyworldPre::new(linked)?;
+
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(%origin, addr = %listener.local_addr()?, "serving the cache");
+    loop {
+        let (client, _) = listener.accept().await?;
+        let pre = pre.clone();
+        let cache_pre = cache_pre.clone();
+        let origin = origin.to_string();
+        let config = config.clone();
+        tokio::task::spawn(async move {
+            let service = service_fn(move |request: hyper::Request<hyper::body::Incoming>| {
+                let span = tracing::info_span!("request", method = %request.method(), uri = %request.uri());
+                let pre = pre.clone();
+                let cache_pre = cache_pre.clone();
+                let origin = origin.clone();
+                let config = config.clone();
+                async move {
+                    if request.uri().path() == "/metrics" {
+                        serve_metrics(&cache_pre, config).await
+                    } else {
+                        handle(pre, origin, config, request).await
+                    }
+                }
+                .instrument(span)
+            });
+            if let Err(err) = http1::Builder::new()
+                .keep_alive(true)
+                .serve_connection(TokioIo::new(client), service)
+                .await
+            {
+                tracing::error!(error = ?err, "error serving connection");
+            }
+        });
+    }
+}
+
+// Runs one request through a new instance of the guest.
+async fn handle(
+    pre: ProxyPre<MyState>,
+    origin: String,
+    config: Arc<HostConfig>,
+    request: hyper::Request<hyper::body::Incoming>,
+) -> Result<hyper::Response<HyperOutgoingBody>> {
+    let wasi_ctx = clock::install(
+        WasiCtxBuilder::new()
+            .inherit_stdio()
+            .env("CACHE_ORIGIN", &origin)
+            .env("CACHE_STORE", CACHE_FILE),
+    )
+    .build();
+    let mut store = new_store(pre.engine(), wasi_ctx, config)?;
+
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let request = store.data_mut().new_incoming_request(Scheme::Http, request)?;
+    let response_out = store.data_mut().new_response_outparam(sender)?;
+
+    let task = tokio::task::spawn(async move {
+        let proxy = pre.instantiate_async(&mut store).await?;
+        proxy
+            .wasi_http_incoming_handler()
+            .call_handle(&mut store, request, response_out)
+            .await
+    });
+
+    match receiver.await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(err)) => Err(err.into()),
+        // The guest returned or trapped without setting a response
+        Err(_) => {
+            let err = match task.await {
+                Ok(Ok(())) => anyhow!("guest never set a response"),
+                Ok(Err(err)) => err,
+                Err(err) => err.into(),
+            };
+            Err(err.context("guest never invoked `response-outparam::set`"))
+        }
+    }
+}
+
+// Reads the counters through a new instance of the guest and renders them
+// together with the host's own measurements.
+async fn serve_metrics(pre: &MyworldPre<MyState>, config: Arc<HostConfig>) -> Result<hyper::Response<HyperOutgoingBody>> {
+    let mut store = new_store(pre.engine(), WasiCtxBuilder::new().build(), config)?;
+    let guest = pre.instantiate_async(&mut store).await?;
+    let stats = guest.call_stats(&mut store, CACHE_FILE).await?;
+    let body: HyperOutgoingBody = Full::new(Bytes::from(metrics::render(stats.as_ref())))
+        .map_err(|never| match never {})
+        .boxed();
+    Ok(hyper::Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body)?)
+}
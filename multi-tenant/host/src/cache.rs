@@ -0,0 +1,41 @@
+use anyhow::{bail, Result};
+use wasmtime::component::{bindgen, InstancePre};
+use wasmtime::Store;
+
+use crate::MyState;
+
+bindgen!({
+    world: "myworld",
+    path: "../../http-cache/guest/wit",
+    async: true,
+    tracing: true,
+    with: {
+        "wasi:keyvalue/store": host_common::wasi::keyvalue::store,
+        "wasi:keyvalue/atomics": host_common::wasi::keyvalue::atomics,
+        "alireza:logging/logging": host_common::alireza::logging::logging,
+    },
+});
+
+// Bucket the cached responses are kept in, inside the tenant's data directory.
+const CACHE_FILE: &str = "data.json";
+
+/// An instance of the http-cache guest.
+pub struct Cache(Myworld);
+
+impl Cache {
+    pub async fn instantiate(pre: &InstancePre<MyState>, store: &mut Store<MyState>) -> Result<Self> {
+        let functions = MyworldPre::new(pre.clone())?.instantiate_async(store).await?;
+        Ok(Cache(functions))
+    }
+
+    /// Takes `<url>` and answers it from the cache, fetching it when needed.
+    pub async fn call(&self, store: &mut Store<MyState>, args: &[String]) -> Result<String> {
+        let [url] = args else {
+            bail!("expected <url>");
+        };
+        match self.0.call_get_or_fetch(store, CACHE_FILE, url).await? {
+            Some(body) => Ok(body),
+            None => Ok(format!("No response for {}", url)),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use host_common::provider::{self, GenerationOptions, Provider};
+use host_common::stream::ModelStream;
+use wasmtime::component::{InstancePre, Linker, Resource, ResourceTable};
+use wasmtime::Store;
+
+use crate::config::HostConfig;
+use crate::MyState;
+
+// The call functions bindgen generates take one argument per parameter,
+// which for `ask` is more than clippy allows.
+#[allow(clippy::too_many_arguments)]
+mod bindings {
+    wasmtime::component::bindgen!({
+        world: "chat",
+        path: "../../ai-history/guest/wit",
+        async: true,
+        tracing: true,
+        with: {
+            "wasi:keyvalue/store": host_common::wasi::keyvalue::store,
+            "wasi:keyvalue/atomics": host_common::wasi::keyvalue::atomics,
+            "alireza:logging/logging": host_common::alireza::logging::logging,
+            "host/model-stream": host_common::stream::ModelStream,
+        },
+    });
+}
+use bindings::*;
+
+// Bucket the chat history is kept in, inside the tenant's data directory.
+const HISTORY_FILE: &str = "data.json";
+
+// Calls of a tenant all continue the one conversation.
+const SESSION: &str = "default";
+
+// Prompts only match earlier ones that differ in case and whitespace,
+// whatever was asked before them.
+const LOOKUP: Lookup = Lookup {
+    strategy: MatchStrategy::Normalized,
+    in_context: false,
+};
+
+// Tenants leave every generation setting to the model.
+const OPTIONS: Options = Options {
+    temperature: None,
+    top_p: None,
+    seed: None,
+    max_tokens: None,
+    system: None,
+    stop: Vec::new(),
+};
+
+// Host side of the ai-history `host` interface. Models are asked through the
+// providers of the tenant, within its network policy and limits like any
+// other request.
+pub struct ChatHost {
+    config: Arc<HostConfig>,
+    table: ResourceTable,
+}
+
+impl ChatHost {
+    pub fn new(config: Arc<HostConfig>) -> Self {
+        ChatHost {
+            config,
+            table: ResourceTable::new(),
+        }
+    }
+}
+
+impl host::Host for ChatHost {
+    async fn stream_model(&mut self, model: String, prompt: String, context: Vec<u64>, options: Options) -> Option<Resource<ModelStream>> {
+        let provider = Provider::for_model(&self.config.providers, &model).within(&self.config.network, &self.config.http);
+        let stream = provider.generate(&model, &prompt, &context, &options.into()).await?;
+        self.table.push(stream).ok()
+    }
+
+    async fn stream_chat(&mut self, model: String, messages: Vec<host::Message>, options: Options) -> Option<Resource<ModelStream>> {
+        let provider = Provider::for_model(&self.config.providers, &model).within(&self.config.network, &self.config.http);
+        let messages: Vec<_> = messages.into_iter().map(provider::Message::from).collect();
+        let stream = provider.chat(&model, &messages, &options.into()).await?;
+        self.table.push(stream).ok()
+    }
+
+    // Tenants run side by side, so tokens are not printed as they arrive.
+    // The whole answer is shown when the call returns.
+    async fn print_token(&mut self, _token: String) {}
+
+    // Tenants match prompts without embeddings, so none are made.
+    async fn embed(&mut self, _text: String) -> Option<Vec<f32>> {
+        None
+    }
+}
+
+// The `types` interface only holds types, but the guest imports it all the same.
+impl alireza::aihistory::types::Host for ChatHost {}
+
+impl host::HostModelStream for ChatHost {
+    async fn next(&mut self, stream: Resource<ModelStream>) -> Result<Option<host::Chunk>, String> {
+        let stream = self.table.get_mut(&stream).map_err(|err| err.to_string())?;
+        let piece = stream.next_piece().await?;
+        Ok(piece.map(|piece| host::Chunk { text: piece.text, context: piece.context }))
+    }
+
+    async fn drop(&mut self, stream: Resource<ModelStream>) -> wasmtime::Result<()> {
+        self.table.delete(stream)?;
+        Ok(())
+    }
+}
+
+impl From<host::Message> for provider::Message {
+    fn from(message: host::Message) -> Self {
+        let role = match message.role {
+            host::Role::System => provider::Role::System,
+            host::Role::User => provider::Role::User,
+            host::Role::Assistant => provider::Role::Assistant,
+        };
+        provider::Message { role, content: message.content }
+    }
+}
+
+impl From<Options> for GenerationOptions {
+    fn from(options: Options) -> Self {
+        GenerationOptions {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+            max_tokens: options.max_tokens,
+            system: options.system,
+            stop: options.stop,
+        }
+    }
+}
+
+pub fn add_to_linker(linker: &mut Linker<MyState>) -> Result<()> {
+    host::add_to_linker(linker, |state: &mut MyState| &mut state.chat)?;
+    alireza::aihistory::types::add_to_linker(linker, |state: &mut MyState| &mut state.chat)
+}
+
+/// An instance of the ai-history guest.
+pub struct History(Chat);
+
+impl History {
+    pub async fn instantiate(pre: &InstancePre<MyState>, store: &mut Store<MyState>) -> Result<Self> {
+        let functions = ChatPre::new(pre.clone())?.instantiate_async(store).await?;
+        Ok(History(functions))
+    }
+
+    /// Takes `<model> <prompt...>` and asks the model, keeping the history.
+    pub async fn call(&self, store: &mut Store<MyState>, args: &[String]) -> Result<String> {
+        let [model, prompt @ ..] = args else {
+            bail!("expected <model> <prompt>");
+        };
+        if prompt.is_empty() {
+            bail!("expected a prompt after the model");
+        }
+        // The context tokens of /api/generate are all the conversation a tenant
+        // needs, but only ollama takes them. Other providers get the messages.
+        let api = if Provider::for_model(&store.data().chat.config.providers, model).takes_context() {
+            Api::Generate
+        } else {
+            Api::Chat
+        };
+        match self.0.call_ask(store, HISTORY_FILE, SESSION, model, &prompt.join(" "), LOOKUP, api, &OPTIONS).await? {
+            Some(answer) => Ok(answer.text),
+            None => Ok("No answer from the model".to_string()),
+        }
+    }
+}
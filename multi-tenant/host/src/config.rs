@@ -0,0 +1,27 @@
+use host_common::config::{GuestLimits, HttpLimits};
+use host_common::outgoing::OutgoingConfig;
+use host_common::policy::NetworkPolicy;
+use host_common::provider::ProviderConfig;
+use serde::Deserialize;
+
+// Settings the host applies to the guest of one tenant.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HostConfig {
+    pub network: NetworkPolicy,
+    pub http: HttpLimits,
+    pub limits: GuestLimits,
+    // Where the models of an ai-history guest are served, as in the
+    // ai-history host's configuration.
+    pub providers: Vec<ProviderConfig>,
+}
+
+impl OutgoingConfig for HostConfig {
+    fn network(&self) -> &NetworkPolicy {
+        &self.network
+    }
+
+    fn http(&self) -> &HttpLimits {
+        &self.http
+    }
+}
@@ -0,0 +1,147 @@
+use std::{error::Error, path::PathBuf, sync::Arc};
+use host_common::{keyvalue, limits, logging, outgoing, precompile};
+use wasmtime::{component::{ResourceTable, Linker}, *};
+use wasmtime_wasi::{IoView, WasiCtx, WasiView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
+
+mod cache;
+mod chat;
+mod config;
+mod manifest;
+mod tenant;
+mod users;
+
+use config::HostConfig;
+use manifest::Manifest;
+use tenant::Tenant;
+
+// State of a single tenant's store. Every tenant has its own, so one guest can
+// never reach the files, environment or network access of another.
+struct MyState {
+    ctx: WasiCtx,
+    table: ResourceTable,
+    http: WasiHttpCtx,
+    keyvalue: keyvalue::KeyValue,
+    chat: chat::ChatHost,
+    logger: logging::GuestLogger,
+    limiter: limits::GuestLimiter,
+    config: Arc<HostConfig>,
+}
+impl IoView for MyState {
+    fn table(&mut self) -> &mut ResourceTable { &mut self.table }
+}
+impl WasiView for MyState {
+    fn ctx(&mut self) -> &mut WasiCtx { &mut self.ctx }
+}
+impl WasiHttpView for MyState {
+    fn ctx(&mut self) -> &mut WasiHttpCtx { &mut self.http }
+
+    // Every outgoing request of the guest is checked against the network policy
+    // of its tenant first, and then sent within the tenant's limits.
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        outgoing::send_request(request, config, self.config.clone(), |_| ())
+    }
+}
+
+impl MyState {
+    fn new(ctx: WasiCtx, data_dir: PathBuf, config: Arc<HostConfig>) -> Self {
+        MyState {
+            ctx,
+            table: ResourceTable::new(),
+            http: WasiHttpCtx::new(),
+            keyvalue: keyvalue::KeyValue::new(data_dir),
+            chat: chat::ChatHost::new(config.clone()),
+            logger: logging::GuestLogger,
+            limiter: limits::GuestLimiter::new(&config.limits),
+            config,
+        }
+    }
+}
+
+// Builds the engine shared by all tenants.
+fn build_engine() -> anyhow::Result<Engine> {
+    let mut engine_config = Config::new();
+    engine_config.wasm_component_model(true).async_support(true);
+    limits::configure_shared(&mut engine_config);
+    let engine = Engine::new(&engine_config)?;
+    limits::start_epoch_ticker(&engine);
+    Ok(engine)
+}
+
+// Links the imports of every world the host supports. A guest only gets to
+// use the ones its own world imports.
+fn build_linker(engine: &Engine) -> anyhow::Result<Linker<MyState>> {
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi::add_to_linker_async(&mut linker)?;
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+    host_common::wasi::keyvalue::store::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::wasi::keyvalue::atomics::add_to_linker(&mut linker, |state: &mut MyState| &mut state.keyvalue)?;
+    host_common::alireza::logging::logging::add_to_linker(&mut linker, |state: &mut MyState| &mut state.logger)?;
+    chat::add_to_linker(&mut linker)?;
+    Ok(linker)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // `host call <tenant> [args...]` makes a single call into one tenant instead
+    // of running the calls listed in the manifest, and `host precompile` compiles
+    // the components of all tenants into the compile cache.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    logging::init();
+    let manifest = Manifest::load()?;
+    if args.first().map(String::as_str) == Some("precompile") {
+        let engine = build_engine()?;
+        for tenant in &manifest.tenants {
+            let compiled = precompile::precompile(&engine, &tenant.component)?;
+            println!("Compiled {} for {} to {}", tenant.component.display(), tenant.name, compiled.display());
+        }
+        return Ok(());
+    }
+    tokio::runtime::Runtime::new()?.block_on(run(manifest, args))
+}
+
+async fn run(manifest: Manifest, args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let engine = build_engine()?;
+    let linker = build_linker(&engine)?;
+    let mut tenants = Vec::new();
+    for config in manifest.tenants {
+        tenants.push(Tenant::new(&engine, &linker, config).await?);
+    }
+
+    if args.first().map(String::as_str) == Some("call") {
+        let [_, name, call @ ..] = args.as_slice() else {
+            return Err("expected call <tenant> [args...]".into());
+        };
+        let tenant = tenants
+            .iter_mut()
+            .find(|tenant| tenant.name == *name)
+            .ok_or_else(|| format!("no tenant named {}", name))?;
+        match tenant.call(call).await {
+            Ok(output) => println!("{}", output),
+            Err(err) => println!("Call failed: {}", limits::describe(&err)),
+        }
+        return Ok(());
+    }
+
+    // Tenants run side by side, each making its own calls in order.
+    let mut tasks = Vec::new();
+    for mut tenant in tenants {
+        tasks.push(tokio::spawn(async move {
+            for call in std::mem::take(&mut tenant.calls) {
+                match tenant.call(&call).await {
+                    Ok(output) => println!("[{}] {:?}", tenant.name, output),
+                    Err(err) => println!("[{}] Call failed: {}", tenant.name, limits::describe(&err)),
+                }
+            }
+        }));
+    }
+    for task in tasks {
+        task.await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtx, WasiCtxBuilder};
+
+use crate::config::HostConfig;
+
+// Default location of the manifest, relative to the working directory.
+// The TENANT_MANIFEST environment variable overrides it.
+pub const MANIFEST_PATH: &str = "tenants.json";
+
+// The tenants one host process runs.
+#[derive(Deserialize, Debug)]
+pub struct Manifest {
+    pub tenants: Vec<TenantConfig>,
+}
+
+// One guest component and everything it is allowed to reach.
+#[derive(Deserialize, Debug)]
+pub struct TenantConfig {
+    pub name: String,
+    pub world: World,
+    // Path of the compiled guest component.
+    pub component: PathBuf,
+    // Directory the tenant's wasi:keyvalue buckets are kept in.
+    // Defaults to `tenants/<name>`.
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub preopens: Vec<Preopen>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    // Calls made when the host runs without arguments, one list of arguments per call.
+    #[serde(default)]
+    pub calls: Vec<Vec<String>>,
+    // The network policy and limits, set per tenant the same way the
+    // single-guest hosts read them from host-config.json.
+    #[serde(flatten)]
+    pub host: HostConfig,
+}
+
+// The worlds this host knows how to call into.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum World {
+    HttpCache,
+    AiHistory,
+    UserData,
+}
+
+// A host directory the guest can see under `guest`.
+#[derive(Deserialize, Debug)]
+pub struct Preopen {
+    pub host: PathBuf,
+    pub guest: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl Manifest {
+    /// Loads the manifest and checks that tenant names are unique.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("TENANT_MANIFEST").unwrap_or_else(|_| MANIFEST_PATH.to_string());
+        let contents = fs::read_to_string(&path).with_context(|| format!("reading {}", path))?;
+        let manifest: Manifest = serde_json::from_str(&contents).with_context(|| format!("parsing {}", path))?;
+        for (i, tenant) in manifest.tenants.iter().enumerate() {
+            if manifest.tenants[..i].iter().any(|other| other.name == tenant.name) {
+                bail!("{} lists the tenant {} twice", path, tenant.name);
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+impl TenantConfig {
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("tenants").join(&self.name))
+    }
+
+    /// Builds the WASI context of the tenant. Guests only see the environment
+    /// and directories listed for them, never those of the host.
+    pub fn wasi_ctx(&self) -> Result<WasiCtx> {
+        let mut builder = WasiCtxBuilder::new();
+        builder.inherit_stdio();
+        for (key, value) in &self.env {
+            builder.env(key, value);
+        }
+        for preopen in &self.preopens {
+            let (dir_perms, file_perms) = if preopen.read_only {
+                (DirPerms::READ, FilePerms::READ)
+            } else {
+                (DirPerms::all(), FilePerms::all())
+            };
+            builder
+                .preopened_dir(&preopen.host, &preopen.guest, dir_perms, file_perms)
+                .with_context(|| format!("opening {} for tenant {}", preopen.host.display(), self.name))?;
+        }
+        Ok(builder.build())
+    }
+}
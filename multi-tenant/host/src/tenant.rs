@@ -0,0 +1,87 @@
+use std::fs;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use host_common::{limits, precompile};
+use wasmtime::component::{InstancePre, Linker};
+use wasmtime::{Engine, Store};
+
+use crate::cache::Cache;
+use crate::chat::History;
+use crate::config::HostConfig;
+use crate::manifest::{TenantConfig, World};
+use crate::users::Users;
+use crate::MyState;
+
+// The instance of a tenant, by the world its guest targets.
+enum Instance {
+    Cache(Cache),
+    History(History),
+    Users(Users),
+}
+
+/// One guest of the host, with a store of its own. A call that traps leaves
+/// the instance unusable, so it is replaced with a fresh one, without touching
+/// the other tenants.
+pub struct Tenant {
+    pub name: String,
+    pub calls: Vec<Vec<String>>,
+    manifest: TenantConfig,
+    config: Arc<HostConfig>,
+    pre: InstancePre<MyState>,
+    store: Store<MyState>,
+    instance: Instance,
+}
+
+impl Tenant {
+    pub async fn new(engine: &Engine, linker: &Linker<MyState>, mut manifest: TenantConfig) -> Result<Self> {
+        let component = precompile::load(engine, &manifest.component)
+            .with_context(|| format!("loading the component of tenant {}", manifest.name))?;
+        let pre = linker.instantiate_pre(&component)?;
+        fs::create_dir_all(manifest.data_dir())?;
+
+        let config = Arc::new(std::mem::take(&mut manifest.host));
+        let (store, instance) = Self::instantiate(&pre, &manifest, &config).await?;
+        Ok(Tenant {
+            name: manifest.name.clone(),
+            calls: std::mem::take(&mut manifest.calls),
+            manifest,
+            config,
+            pre,
+            store,
+            instance,
+        })
+    }
+
+    async fn instantiate(
+        pre: &InstancePre<MyState>,
+        manifest: &TenantConfig,
+        config: &Arc<HostConfig>,
+    ) -> Result<(Store<MyState>, Instance)> {
+        let state = MyState::new(manifest.wasi_ctx()?, manifest.data_dir(), config.clone());
+        let mut store = Store::new(pre.engine(), state);
+        store.limiter(|state| &mut state.limiter);
+        limits::arm(&mut store, &config.limits)?;
+        let instance = match manifest.world {
+            World::HttpCache => Instance::Cache(Cache::instantiate(pre, &mut store).await?),
+            World::AiHistory => Instance::History(History::instantiate(pre, &mut store).await?),
+            World::UserData => Instance::Users(Users::instantiate(pre, &mut store).await?),
+        };
+        Ok((store, instance))
+    }
+
+    /// Calls into the guest with arguments in the form its world expects.
+    #[tracing::instrument(skip(self, args), fields(tenant = %self.name))]
+    pub async fn call(&mut self, args: &[String]) -> Result<String> {
+        limits::arm(&mut self.store, &self.config.limits)?;
+        let result = match &self.instance {
+            Instance::Cache(cache) => cache.call(&mut self.store, args).await,
+            Instance::History(history) => history.call(&mut self.store, args).await,
+            Instance::Users(users) => users.call(&mut self.store, args).await,
+        };
+        if result.is_err() {
+            (self.store, self.instance) = Self::instantiate(&self.pre, &self.manifest, &self.config).await?;
+        }
+        result
+    }
+}
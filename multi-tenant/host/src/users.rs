@@ -0,0 +1,50 @@
+use anyhow::{bail, Result};
+use wasmtime::component::{bindgen, InstancePre};
+use wasmtime::Store;
+
+use crate::MyState;
+
+bindgen!({
+    world: "myworld",
+    path: "../../guest/wit",
+    async: true,
+    tracing: true,
+});
+
+/// An instance of the user-data demo guest.
+pub struct Users(Myworld);
+
+impl Users {
+    pub async fn instantiate(pre: &InstancePre<MyState>, store: &mut Store<MyState>) -> Result<Self> {
+        let functions = MyworldPre::new(pre.clone())?.instantiate_async(store).await?;
+        Ok(Users(functions))
+    }
+
+    /// Takes `get-name <first-name> <last-name>` or
+    /// `change-user <first-name> <last-name> <age> [grades...]`.
+    pub async fn call(&self, store: &mut Store<MyState>, args: &[String]) -> Result<String> {
+        let (command, first_name, last_name, rest) = match args {
+            [command, first_name, last_name, rest @ ..] => (command, first_name, last_name, rest),
+            _ => bail!("expected get-name or change-user, followed by <first-name> <last-name>"),
+        };
+        let mut user = UserData {
+            first_name: first_name.clone(),
+            last_name: last_name.clone(),
+            age: 0,
+            grades: Vec::new(),
+        };
+        match command.as_str() {
+            "get-name" => Ok(self.0.call_get_name(store, &user).await?),
+            "change-user" => {
+                let [age, grades @ ..] = rest else {
+                    bail!("expected <age> after the name");
+                };
+                user.age = age.parse()?;
+                user.grades = grades.iter().map(|grade| grade.parse()).collect::<Result<_, _>>()?;
+                let changed = self.0.call_change_user(store, &user).await?;
+                Ok(format!("{:?}", changed))
+            }
+            _ => bail!("unknown call {}, expected get-name or change-user", command),
+        }
+    }
+}
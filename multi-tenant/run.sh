@@ -0,0 +1,6 @@
+#!/bin/bash
+
+cd ../http-cache/guest; cargo build --release --target=wasm32-wasip2
+cd ../../ai-history/guest; cargo build --release --target=wasm32-wasip2
+cd ../../guest; cargo build --release --target=wasm32-wasip2
+cd ../multi-tenant/host; cargo run
@@ -1,15 +1,10 @@
-#![feature(wasip1)]
-
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use serde::{Serialize, Deserialize};
-use std::net::TcpStream;
-use std::io::prelude::*;
-use url::Url;
-
-
-use reqwest::blocking::get;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ::wasi::http::types::ErrorCode;
+use guest_common::fetch;
 
 #[derive(Serialize, Deserialize)]
 struct CacheEntry {
@@ -43,40 +38,23 @@ impl FileCache {
         }
     }
 
-    fn manual_get(&mut self, url: String) -> Option<String> {
-        // Send the GET request and return None if there's an error
-        let response = get(&url).ok()?
-;
-
-        // Check that the request was successful
-        if !response.status().is_success() {
-            return None;
-        }
-
-        // Read the response body as bytes, returning None on error
-        let text = response.text().ok()?;
-
-        println!("Fetched {} bytes", text.len());
-        Some(text)
-    }
-
-    fn write_to_file(&mut self, data: String, file_name: String) {
+    fn write_to_file(&self, data: &str, file_name: &str) {
         if let Ok(mut file) = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(&file_name)
+        .open(file_name)
     {
         let _ = file.write_all(data.as_bytes());
     }
     }
 
-    fn read_from_file(&mut self, file_name: String) -> String {
-        match File::open(&file_name) {
+    fn read_from_file(&self, file_name: &str) -> String {
+        match File::open(file_name) {
             Ok(mut file) => {
                 let mut contents = String::new();
                 if file.read_to_string(&mut contents).is_ok() {
-                    return contents;
+                    contents
                 } else {
                     String::from("")
                 }
@@ -128,10 +106,8 @@ impl FileCache {
     pub fn get_response(&self, key: &str, current_time: u64) -> Option<String> {
         let data = self.load_cache();
         if let Some(entry) = data.entries.get(key) {
-            if let Some(expiry) = entry.expiry {
-                if current_time > expiry {
-                    return None;
-                }
+            if entry.expiry.is_some_and(|expiry| current_time > expiry) {
+                return None;
             }
             Some(entry.body.clone())
         } else {
@@ -151,46 +127,6 @@ impl FileCache {
         let data = CacheData::new();
         self.save_cache(&data);
     }
-
-    pub fn manual_get(&self, url: &str) -> Option<String> {
-        // Parse the URL
-        let url = Url::parse(url).ok()?;
-        let host = url.host_str()?;
-        let port = url.port_or_known_default()?;
-        let addr = format!("{}:{}", host, port);
-
-        // Determine the path (and query, if any)
-        let mut path = url.path().to_string();
-        if let Some(query) = url.query() {
-            path.push('?');
-            path.push_str(query);
-        }
-        if path.is_empty() {
-            path = "/".to_string();
-        }
-
-        // Connect to the host
-        let mut stream = TcpStream::connect(&addr).ok()?;
-
-        // Construct a minimal HTTP GET request.
-        let request = format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
-            path, host
-        );
-        stream.write_all(request.as_bytes()).ok()?;
-
-        // Read the entire response.
-        let mut response = Vec::new();
-        stream.read_to_end(&mut response).ok()?;
-        let response = String::from_utf8(response).ok()?;
-
-        // Separate HTTP headers from the body.
-        if let Some(pos) = response.find("\r\n\r\n") {
-            let body = &response[(pos + 4)..];
-            return Some(body.to_string());
-        }
-        Some(response)
-    }
 }
 
 
@@ -204,22 +140,32 @@ wit_bindgen::generate!({
 struct MyHost;
 
 impl Guest for MyHost {
-    fn get_or_fetch(file_path: String, key: String, current_time: u64) -> Option<String> {
+    fn get_or_fetch(file_path: String, key: String) -> Option<String> {
+        // The standard library reads the time from wasi:clocks/wall-clock
+        let current_time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
         let cache = FileCache::new(file_path);
         if let Some(cached) = cache.get_response(&key, current_time) {
             println!("Cache hit for {}", key);
             Some(cached)
         } else {
             println!("Cache miss or stale entry for {}. Fetching from network...", key);
-            match self.manual_get(&key) {
-                Some(body) => {
+            match fetch::get(&key, &[]) {
+                Ok(response) if (200..300).contains(&response.status) => {
                     // For demonstration, we set no expiry.
                     // In practice, you might set an expiry based on headers.
-                    cache.add_response(&key, &body, None, None, None);
-                    Some(body)
+                    cache.add_response(&key, &response.body, None, None, None);
+                    Some(response.body)
+                },
+                Ok(response) => {
+                    eprintln!("Failed to fetch response from network for {}: status {}", key, response.status);
+                    None
+                },
+                Err(ErrorCode::HttpRequestDenied) => {
+                    eprintln!("Failed to fetch response from network for {}: denied by the host network policy", key);
+                    None
                 },
-                None => {
-                    eprintln!("Failed to fetch response from network for {}", key);
+                Err(err) => {
+                    eprintln!("Failed to fetch response from network for {}: {}", key, err);
                     None
                 }
             }
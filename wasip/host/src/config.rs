@@ -0,0 +1,22 @@
+use host_common::config::HttpLimits;
+use host_common::outgoing::OutgoingConfig;
+use host_common::policy::NetworkPolicy;
+use serde::Deserialize;
+
+// Settings the host applies to every guest it runs.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HostConfig {
+    pub network: NetworkPolicy,
+    pub http: HttpLimits,
+}
+
+impl OutgoingConfig for HostConfig {
+    fn network(&self) -> &NetworkPolicy {
+        &self.network
+    }
+
+    fn http(&self) -> &HttpLimits {
+        &self.http
+    }
+}
@@ -1,38 +1,34 @@
-use std::{fs, error::Error};
-use wasmtime::{component::{ResourceTable, bindgen, Component, Linker}, *};
+use std::{error::Error, sync::Arc};
+use host_common::{clock, logging, outgoing, precompile};
+use wasmtime::{component::{ResourceTable, bindgen, Linker}, *};
 use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use reqwest::blocking::get;
-bindgen!("myworld" in "../guest-cache/wit/witfile.wit");
+
+mod config;
+
+use config::HostConfig;
+
+bindgen!({
+    world: "myworld",
+    path: "../guest-cache/wit/witfile.wit",
+    async: true,
+    tracing: true,
+});
 
 struct HostComponent;
 
 // Implementation of the host interface defined in the wit file.
 impl host::Host for HostComponent {
-    fn multiply(&mut self, a: f32, b: f32) -> f32 {
+    async fn multiply(&mut self, a: f32, b: f32) -> f32 {
         a * b
     }
 
-    fn manual_get(&mut self, url: String) -> Option<String> {
-        // Send the GET request and return None if there's an error
-        let response = get(&url).ok()?
-;
-
-        // Check that the request was successful
-        if !response.status().is_success() {
-            return None;
-        }
-
-        // Read the response body as bytes, returning None on error
-        let text = response.text().ok()?;
-
-        println!("Fetched {} bytes", text.len());
-        Some(text)
-    }
-
-    fn write_to_file(&mut self, data: String, file_name: String) {
+    async fn write_to_file(&mut self, data: String, file_name: String) {
         if let Ok(mut file) = OpenOptions::new()
         .write(true)
         .create(true)
@@ -43,7 +39,7 @@ impl host::Host for HostComponent {
     }
     }
 
-    fn read_from_file(&mut self, file_name: String) -> String {
+    async fn read_from_file(&mut self, file_name: String) -> String {
         match File::open(&file_name) {
             Ok(mut file) => {
                 let mut contents = String::new();
@@ -61,7 +57,9 @@ impl host::Host for HostComponent {
 struct MyState {
     ctx: WasiCtx,
     table: ResourceTable,
+    http: WasiHttpCtx,
     host: HostComponent,
+    config: Arc<HostConfig>,
 }
 impl IoView for MyState {
     fn table(&mut self) -> &mut ResourceTable { &mut self.table }
@@ -69,13 +67,52 @@ impl IoView for MyState {
 impl WasiView for MyState {
     fn ctx(&mut self) -> &mut WasiCtx { &mut self.ctx }
 }
+impl WasiHttpView for MyState {
+    fn ctx(&mut self) -> &mut WasiHttpCtx { &mut self.http }
+
+    // The guest fetches through wasi:http, so every request it makes is checked
+    // against the network policy and sent within the configured limits.
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        outgoing::send_request(request, config, self.config.clone(), |_| ())
+    }
+}
+
+// Path of the compiled guest component, relative to the host crate.
+const GUEST_PATH: &str = "../guest-cache/target/wasm32-wasip2/release/guest_cache.wasm";
+
+// Builds the engine guests run on. Compiled components are only reusable by
+// engines built the same way.
+fn build_engine() -> anyhow::Result<Engine> {
+    Engine::new(Config::new().wasm_component_model(true).async_support(true))
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let engine = Engine::new(Config::new().wasm_component_model(true))?;
-    // let bytes = fs::read("../guest/target/wasm32-wasip2/release/guest.wasm")?;
-    let bytes = fs::read("../guest-cache/target/wasm32-wasip2/release/guest_cache.wasm")?;
-    let component = Component::new(&engine, &bytes)?;
-    let wasi_ctx = WasiCtxBuilder::new().inherit_stdio().build();
+    // `host precompile [wasm]` compiles the guest into the compile cache and exits.
+    let args: Vec<String> = std::env::args().collect();
+    logging::init();
+    if args.get(1).map(String::as_str) == Some("precompile") {
+        let wasm_path = args.get(2).map(String::as_str).unwrap_or(GUEST_PATH);
+        let compiled = precompile::precompile(&build_engine()?, wasm_path)?;
+        println!("Compiled {} to {}", wasm_path, compiled.display());
+        return Ok(());
+    }
+    clock::init()?;
+    let config = Arc::new(host_common::config::load::<HostConfig>()?);
+    tokio::runtime::Runtime::new()?.block_on(run(config))
+}
+
+// Guest calls run on the tokio runtime, so waiting on a fetch does not hold
+// on to a thread. With MOCK_CLOCK set, the clock is moved forward by the given
+// seconds before each request.
+async fn run(config: Arc<HostConfig>) -> Result<(), Box<dyn Error>> {
+    let engine = build_engine()?;
+    // let component = precompile::load(&engine, "../guest/target/wasm32-wasip2/release/guest.wasm")?;
+    let component = precompile::load(&engine, GUEST_PATH)?;
+    let wasi_ctx = clock::install(WasiCtxBuilder::new().inherit_stdio()).build();
 
 
 
@@ -83,29 +120,33 @@ fn main() -> Result<(), Box<dyn Error>> {
          MyState {
             ctx: wasi_ctx,
             table: ResourceTable::new(),
-            host: HostComponent {},
+            http: WasiHttpCtx::new(),
+            host: HostComponent,
+            config,
 
          });
     let mut linker = Linker::new(&engine);
-    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+    wasmtime_wasi::add_to_linker_async(&mut linker)?;
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
     host::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
 
-    let functions = Myworld::instantiate(&mut store, &component, &linker)?;
-    let result1 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888", 1000);
-    let result2 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/leisure_data.csv", 1000);
-    let result3 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/config.json", 1000);
-    let result4 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/chart.plugin.js", 1000);
-    let result5 = functions.call_get_or_fetch(&mut store, "./data.json", "http://localhost:8888/q1.jpg", 1000);
-
-
-    println!("{:?}", result1.unwrap().unwrap());
-    println!("{:?}", result2.unwrap().unwrap());
-    println!("{:?}", result3.unwrap().unwrap());
-    println!("{:?}", result4.unwrap().unwrap());
-    // println!("{:?}", result5.unwrap().unwrap());
+    let functions = Myworld::instantiate_async(&mut store, &component, &linker).await?;
+    let requests = [
+        ("http://localhost:8888", 0),
+        ("http://localhost:8888/leisure_data.csv", 1000),
+        ("http://localhost:8888/config.json", 0),
+        ("http://localhost:8888/chart.plugin.js", 0),
+        // ("http://localhost:8888/q1.jpg", 0),
+    ];
+    for (url, elapsed) in requests {
+        clock::advance(elapsed);
+        match functions.call_get_or_fetch(&mut store, "./data.json", url).await {
+            Ok(Some(body)) => println!("{:?}", body),
+            Ok(None) => println!("No response for {}", url),
+            Err(err) => println!("Call for {} failed: {:#}", url, err),
+        }
+    }
 
-    // let mut host = HostComponent{};
-    // host.write_to_file(result5.unwrap().unwrap(), String::from("q1.jpg"));
     Ok(())
 }
 